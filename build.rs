@@ -1,19 +1,37 @@
 use std::env;
 
 fn main() {
+    println!("cargo:rustc-check-cfg=cfg(have_min_max_version)");
+    println!("cargo:rustc-check-cfg=cfg(have_tls13)");
+    println!("cargo:rustc-check-cfg=cfg(have_ossl111)");
+    println!("cargo:rustc-check-cfg=cfg(have_ossl300)");
+
     if let Ok(version) = env::var("DEP_OPENSSL_VERSION_NUMBER") {
         let version = u64::from_str_radix(&version, 16).unwrap();
 
-        if version >= 0x1_01_00_00_0 {
+        if version >= 0x1010_0000 {
             println!("cargo:rustc-cfg=have_min_max_version");
         }
+
+        if version >= 0x1010_1000 {
+            println!("cargo:rustc-cfg=have_tls13");
+            println!("cargo:rustc-cfg=have_ossl111");
+        }
+
+        if version >= 0x3000_0000 {
+            println!("cargo:rustc-cfg=have_ossl300");
+        }
     }
 
     if let Ok(version) = env::var("DEP_OPENSSL_LIBRESSL_VERSION_NUMBER") {
         let version = u64::from_str_radix(&version, 16).unwrap();
 
-        if version >= 0x2_06_01_00_0 {
+        if version >= 0x2060_1000 {
             println!("cargo:rustc-cfg=have_min_max_version");
         }
+
+        if version >= 0x3040_0000 {
+            println!("cargo:rustc-cfg=have_tls13");
+        }
     }
 }
@@ -0,0 +1,208 @@
+use std::fmt;
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::pin::Pin;
+use std::ptr;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use {HandshakeError, MidHandshakeTlsStream, Result, StreamSlot, TlsConnector, TlsStream};
+
+// An adapter which exposes an asynchronous stream through the blocking `Read` and `Write` traits.
+//
+// While the stream is being polled, operations which would block report a `WouldBlock` error.
+// Outside of a call to one of `AsyncTlsStream`'s poll methods, every operation fails.
+#[derive(Debug)]
+pub(crate) struct AllowStd<S> {
+    inner: S,
+    context: *mut (),
+}
+
+// The context pointer is only set while the owning stream is being polled.
+unsafe impl<S: Send> Send for AllowStd<S> {}
+unsafe impl<S: Sync> Sync for AllowStd<S> {}
+
+impl<S> AllowStd<S> {
+    fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S: Unpin> AllowStd<S> {
+    fn with_context<F, R>(&mut self, f: F) -> io::Result<R>
+    where
+        F: FnOnce(&mut Context<'_>, Pin<&mut S>) -> Poll<io::Result<R>>,
+    {
+        if self.context.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "stream used outside of a poll",
+            ));
+        }
+        let cx = unsafe { &mut *(self.context as *mut Context<'_>) };
+        match f(cx, Pin::new(&mut self.inner)) {
+            Poll::Ready(r) => r,
+            Poll::Pending => Err(io::Error::from(io::ErrorKind::WouldBlock)),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> Read for AllowStd<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buf = ReadBuf::new(buf);
+        self.with_context(|cx, s| s.poll_read(cx, &mut buf))?;
+        Ok(buf.filled().len())
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Write for AllowStd<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.with_context(|cx, s| s.poll_write(cx, buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.with_context(|cx, s| s.poll_flush(cx))
+    }
+}
+
+fn cvt<T>(r: io::Result<T>) -> Poll<io::Result<T>> {
+    match r {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+        r => Poll::Ready(r),
+    }
+}
+
+/// A TLS stream which implements tokio's `AsyncRead` and `AsyncWrite`.
+pub struct AsyncTlsStream<S>(TlsStream<AllowStd<S>>);
+
+impl<S: fmt::Debug> fmt::Debug for AsyncTlsStream<S> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, fmt)
+    }
+}
+
+impl<S> AsyncTlsStream<S> {
+    /// Returns a shared reference to the inner stream.
+    pub fn get_ref(&self) -> &S {
+        self.0.get_ref().get_ref()
+    }
+
+    /// Returns a mutable reference to the inner stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        self.0.get_mut().get_mut()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncTlsStream<S> {
+    fn with_context<F, R>(&mut self, cx: &mut Context<'_>, f: F) -> Poll<io::Result<R>>
+    where
+        F: FnOnce(&mut TlsStream<AllowStd<S>>) -> io::Result<R>,
+    {
+        self.0.get_mut().context = cx as *mut Context<'_> as *mut ();
+        let r = f(&mut self.0);
+        self.0.get_mut().context = ptr::null_mut();
+        cvt(r)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for AsyncTlsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        // The stream never de-initializes the buffer, and initializes the bytes it reads.
+        let n = match self
+            .get_mut()
+            .with_context(cx, |s| s.read_uninit(unsafe { buf.unfilled_mut() }))
+        {
+            Poll::Ready(Ok(n)) => n,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        };
+        unsafe { buf.assume_init(n) };
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for AsyncTlsStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().with_context(cx, |s| s.write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().with_context(cx, |s| s.flush())
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.with_context(cx, |s| s.shutdown()) {
+            Poll::Ready(Ok(())) => {}
+            r => return r,
+        }
+        Pin::new(this.0.get_mut().get_mut()).poll_shutdown(cx)
+    }
+}
+
+enum Handshake<S> {
+    Start(TlsConnector, String, S),
+    Mid(MidHandshakeTlsStream<AllowStd<S>>),
+    Done,
+}
+
+/// A future which resolves to an `AsyncTlsStream` once the TLS handshake has completed.
+pub struct ConnectAsync<S>(Handshake<S>);
+
+impl<S> ConnectAsync<S> {
+    pub(crate) fn new(connector: TlsConnector, domain: &str, stream: S) -> ConnectAsync<S> {
+        ConnectAsync(Handshake::Start(connector, domain.to_owned(), stream))
+    }
+}
+
+impl<S> fmt::Debug for ConnectAsync<S> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ConnectAsync").finish()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Future for ConnectAsync<S> {
+    type Output = Result<AsyncTlsStream<S>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let context = cx as *mut Context<'_> as *mut ();
+        let result = match mem::replace(&mut this.0, Handshake::Done) {
+            Handshake::Start(connector, domain, inner) => {
+                connector.start_connect(&domain, StreamSlot::new(AllowStd { inner, context }, None))
+            }
+            Handshake::Mid(mut s) => {
+                s.get_mut().context = context;
+                s.handshake()
+            }
+            Handshake::Done => panic!("ConnectAsync polled after completion"),
+        };
+
+        match result {
+            Ok(mut s) => {
+                s.get_mut().context = ptr::null_mut();
+                Poll::Ready(Ok(AsyncTlsStream(s)))
+            }
+            Err(HandshakeError::WouldBlock(mut s)) => {
+                s.get_mut().context = ptr::null_mut();
+                this.0 = Handshake::Mid(s);
+                Poll::Pending
+            }
+            Err(HandshakeError::Failure { error: e, .. }) => Poll::Ready(Err(e)),
+        }
+    }
+}
@@ -0,0 +1,594 @@
+//! A minimal DER reader, used to pull fields out of certificates on platforms whose APIs don't
+//! expose them.
+
+use std::char;
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str;
+
+use {NamedGroup, PublicKeyInfo, SubjectAltName};
+
+pub const BOOLEAN: u8 = 0x01;
+pub const INTEGER: u8 = 0x02;
+pub const BIT_STRING: u8 = 0x03;
+pub const OCTET_STRING: u8 = 0x04;
+pub const OBJECT_IDENTIFIER: u8 = 0x06;
+pub const UTF8_STRING: u8 = 0x0c;
+pub const NUMERIC_STRING: u8 = 0x12;
+pub const PRINTABLE_STRING: u8 = 0x13;
+pub const T61_STRING: u8 = 0x14;
+pub const IA5_STRING: u8 = 0x16;
+pub const UTC_TIME: u8 = 0x17;
+pub const GENERALIZED_TIME: u8 = 0x18;
+pub const VISIBLE_STRING: u8 = 0x1a;
+pub const UNIVERSAL_STRING: u8 = 0x1c;
+pub const BMP_STRING: u8 = 0x1e;
+pub const SEQUENCE: u8 = 0x30;
+pub const SET: u8 = 0x31;
+
+/// A cursor over a sequence of DER-encoded values.
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+
+    pub fn peek_tag(&self) -> Option<u8> {
+        self.data.first().cloned()
+    }
+
+    /// Reads the next value, returning its tag, its contents, and its complete encoding.
+    pub fn read_any(&mut self) -> Option<(u8, &'a [u8], &'a [u8])> {
+        let tag = *self.data.first()?;
+        let first = *self.data.get(1)? as usize;
+        let (len, header) = if first < 0x80 {
+            (first, 2)
+        } else {
+            let count = first & 0x7f;
+            if count == 0 || count > 4 {
+                return None;
+            }
+            let bytes = self.data.get(2..2 + count)?;
+            let len = bytes.iter().fold(0, |len, &b| (len << 8) | b as usize);
+            (len, 2 + count)
+        };
+        let end = header.checked_add(len)?;
+        let raw = self.data.get(..end)?;
+        self.data = &self.data[end..];
+        Some((tag, &raw[header..], raw))
+    }
+
+    /// Reads the next value, which must have the specified tag, returning its contents.
+    pub fn read(&mut self, tag: u8) -> Option<&'a [u8]> {
+        match self.read_any()? {
+            (t, contents, _) if t == tag => Some(contents),
+            _ => None,
+        }
+    }
+
+    /// Reads the next value if it has the specified tag.
+    pub fn read_optional(&mut self, tag: u8) -> Option<&'a [u8]> {
+        if self.peek_tag() == Some(tag) {
+            self.read(tag)
+        } else {
+            None
+        }
+    }
+}
+
+/// The fields of an X.509 `TBSCertificate`.
+pub struct TbsCertificate<'a> {
+    /// The serial number as unsigned big-endian bytes.
+    pub serial_number: &'a [u8],
+    /// The contents of the issuer `Name`.
+    pub issuer: &'a [u8],
+    /// The contents of the subject `Name`.
+    pub subject: &'a [u8],
+    /// The start of the validity period, in seconds since the Unix epoch.
+    pub not_before: i64,
+    /// The end of the validity period, in seconds since the Unix epoch.
+    pub not_after: i64,
+    /// The complete encoding of the `SubjectPublicKeyInfo`.
+    pub spki: &'a [u8],
+    /// The contents of the `Extensions`, which are empty if the certificate has none.
+    pub extensions: &'a [u8],
+}
+
+pub fn parse_certificate<'a>(der: &'a [u8]) -> Option<TbsCertificate<'a>> {
+    let mut cert = Reader::new(Reader::new(der).read(SEQUENCE)?);
+    let mut tbs = Reader::new(cert.read(SEQUENCE)?);
+
+    // version
+    tbs.read_optional(0xa0);
+    let serial_number = match tbs.read(INTEGER)? {
+        // The leading zero only clears the sign bit of a positive serial.
+        [0, rest @ ..] if !rest.is_empty() => rest,
+        serial_number => serial_number,
+    };
+    // signature
+    tbs.read(SEQUENCE)?;
+    let issuer = tbs.read(SEQUENCE)?;
+    let mut validity = Reader::new(tbs.read(SEQUENCE)?);
+    let not_before = read_time(&mut validity)?;
+    let not_after = read_time(&mut validity)?;
+    let subject = tbs.read(SEQUENCE)?;
+    let (tag, _, spki) = tbs.read_any()?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    // issuerUniqueID and subjectUniqueID
+    tbs.read_optional(0x81);
+    tbs.read_optional(0x82);
+    let extensions = match tbs.read_optional(0xa3) {
+        Some(extensions) => Reader::new(extensions).read(SEQUENCE)?,
+        None => &[],
+    };
+
+    Some(TbsCertificate {
+        serial_number,
+        issuer,
+        subject,
+        not_before,
+        not_after,
+        spki,
+        extensions,
+    })
+}
+
+/// Returns the value of the extension with the specified OID in a certificate's `Extensions`, or
+/// `Some(None)` if it has no such extension.
+fn extension<'a>(extensions: &'a [u8], oid: &[u8]) -> Option<Option<&'a [u8]>> {
+    let mut extensions = Reader::new(extensions);
+    while extensions.peek_tag().is_some() {
+        let mut extension = Reader::new(extensions.read(SEQUENCE)?);
+        let id = extension.read(OBJECT_IDENTIFIER)?;
+        // critical
+        extension.read_optional(BOOLEAN);
+        let value = extension.read(OCTET_STRING)?;
+        if id == oid {
+            return Some(Some(value));
+        }
+    }
+    Some(None)
+}
+
+/// Returns the `GeneralName`s in the subject alternative names of a certificate's `Extensions`, as
+/// their context-specific tags and contents.
+fn general_names(extensions: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut names = vec![];
+    // subjectAltName
+    if let Some(value) = extension(extensions, b"\x55\x1d\x11")? {
+        let mut general_names = Reader::new(Reader::new(value).read(SEQUENCE)?);
+        while general_names.peek_tag().is_some() {
+            let (tag, name, _) = general_names.read_any()?;
+            names.push((tag, name));
+        }
+    }
+    Some(names)
+}
+
+/// Returns whether a certificate's `Extensions` include a TLS feature extension requiring the
+/// `status_request` extension, which is known as OCSP must-staple.
+///
+/// See [RFC 7633](https://tools.ietf.org/html/rfc7633).
+pub fn must_staple(extensions: &[u8]) -> Option<bool> {
+    // id-pe-tlsfeature
+    let value = match extension(extensions, b"\x2b\x06\x01\x05\x05\x07\x01\x18")? {
+        Some(value) => value,
+        None => return Some(false),
+    };
+    let mut features = Reader::new(Reader::new(value).read(SEQUENCE)?);
+    while features.peek_tag().is_some() {
+        // status_request
+        if features.read(INTEGER)? == [5] {
+            return Some(true);
+        }
+    }
+    Some(false)
+}
+
+/// Returns the IP addresses in the subject alternative names of a certificate's `Extensions`, as
+/// their 4 or 16 octets.
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+pub fn ip_addresses(extensions: &[u8]) -> Option<Vec<&[u8]>> {
+    let names = general_names(extensions)?;
+    // iPAddress is an implicitly tagged OCTET STRING.
+    Some(
+        names
+            .into_iter()
+            .filter(|&(tag, _)| tag == 0x87)
+            .map(|(_, address)| address)
+            .collect(),
+    )
+}
+
+/// Returns the supported subject alternative names of a certificate's `Extensions`.
+///
+/// Names of other types, such as `otherName` or `directoryName`, are skipped.
+pub fn subject_alt_names(extensions: &[u8]) -> Option<Vec<SubjectAltName>> {
+    let mut names = vec![];
+    for (tag, name) in general_names(extensions)? {
+        let name = match tag {
+            // rfc822Name, dNSName and uniformResourceIdentifier are implicitly tagged IA5Strings.
+            0x81 => SubjectAltName::Email(ia5_string(name)?),
+            0x82 => SubjectAltName::DnsName(ia5_string(name)?),
+            0x86 => SubjectAltName::Uri(ia5_string(name)?),
+            0x87 => SubjectAltName::IpAddress(ip_address(name)?),
+            _ => continue,
+        };
+        names.push(name);
+    }
+    Some(names)
+}
+
+/// Returns the algorithm and size of a complete `SubjectPublicKeyInfo`.
+///
+/// Returns `None` for algorithms and curves without a `PublicKeyInfo` representation.
+pub fn public_key_info(spki: &[u8]) -> Option<PublicKeyInfo> {
+    let mut spki = Reader::new(Reader::new(spki).read(SEQUENCE)?);
+    let mut algorithm = Reader::new(spki.read(SEQUENCE)?);
+    let oid = algorithm.read(OBJECT_IDENTIFIER)?;
+    let info = match oid {
+        // rsaEncryption and id-RSASSA-PSS
+        b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x01" | b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x0a" => {
+            // The key is an RSAPublicKey wrapped in a BIT STRING with no unused bits.
+            let key = match spki.read(BIT_STRING)? {
+                [0, key @ ..] => key,
+                _ => return None,
+            };
+            let mut key = Reader::new(Reader::new(key).read(SEQUENCE)?);
+            let modulus = key.read(INTEGER)?;
+            let start = modulus.iter().position(|&b| b != 0)?;
+            let bits = (modulus.len() - start) * 8 - modulus[start].leading_zeros() as usize;
+            PublicKeyInfo::Rsa { bits: bits as u32 }
+        }
+        // id-ecPublicKey, whose parameters name the curve
+        b"\x2a\x86\x48\xce\x3d\x02\x01" => {
+            let curve = match algorithm.read(OBJECT_IDENTIFIER)? {
+                b"\x2a\x86\x48\xce\x3d\x03\x01\x07" => NamedGroup::Secp256r1,
+                b"\x2b\x81\x04\x00\x22" => NamedGroup::Secp384r1,
+                b"\x2b\x81\x04\x00\x23" => NamedGroup::Secp521r1,
+                _ => return None,
+            };
+            PublicKeyInfo::Ecdsa { curve }
+        }
+        b"\x2b\x65\x70" => PublicKeyInfo::Ed25519,
+        b"\x2b\x65\x71" => PublicKeyInfo::Ed448,
+        _ => return None,
+    };
+    Some(info)
+}
+
+fn ia5_string(value: &[u8]) -> Option<String> {
+    if !value.is_ascii() {
+        return None;
+    }
+    str::from_utf8(value).ok().map(|s| s.to_string())
+}
+
+fn ip_address(value: &[u8]) -> Option<IpAddr> {
+    match value.len() {
+        4 => Some(IpAddr::V4(Ipv4Addr::new(
+            value[0], value[1], value[2], value[3],
+        ))),
+        16 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(value);
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
+
+/// Reads a `Time`, returning it in seconds since the Unix epoch.
+fn read_time(reader: &mut Reader) -> Option<i64> {
+    let (tag, value, _) = reader.read_any()?;
+    let (year, rest) = match tag {
+        UTC_TIME if value.len() == 13 => {
+            let year = digits(&value[..2])?;
+            // RFC 5280 maps two-digit years to 1950 through 2049.
+            let year = if year >= 50 { 1900 + year } else { 2000 + year };
+            (year, &value[2..])
+        }
+        GENERALIZED_TIME if value.len() == 15 => (digits(&value[..4])?, &value[4..]),
+        _ => return None,
+    };
+    if rest[10] != b'Z' {
+        return None;
+    }
+
+    let month = digits(&rest[0..2])?;
+    let day = digits(&rest[2..4])?;
+    let hour = digits(&rest[4..6])?;
+    let minute = digits(&rest[6..8])?;
+    let second = digits(&rest[8..10])?;
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+fn digits(value: &[u8]) -> Option<i64> {
+    value.iter().try_fold(0, |n, &b| match b {
+        b'0'..=b'9' => Some(n * 10 + i64::from(b - b'0')),
+        _ => None,
+    })
+}
+
+// Returns the number of days between the Unix epoch and the specified date in the proleptic
+// Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Formats a DER-encoded `Name` as an [RFC 4514] string.
+///
+/// [RFC 4514]: https://tools.ietf.org/html/rfc4514
+#[cfg_attr(
+    any(target_os = "macos", target_os = "ios", target_os = "windows"),
+    allow(dead_code)
+)]
+pub fn distinguished_name(der: &[u8]) -> Option<String> {
+    name_to_string(Reader::new(der).read(SEQUENCE)?)
+}
+
+/// Formats the contents of a `Name` as an [RFC 4514] string.
+///
+/// Values which aren't valid in their string type are converted lossily.
+///
+/// [RFC 4514]: https://tools.ietf.org/html/rfc4514
+pub fn name_to_string(name: &[u8]) -> Option<String> {
+    let mut rdns = vec![];
+    let mut reader = Reader::new(name);
+    while reader.peek_tag().is_some() {
+        rdns.push(reader.read(SET)?);
+    }
+
+    let mut out = String::new();
+    // RFC 4514 lists the RDNs in the reverse of their encoded order.
+    for (i, rdn) in rdns.iter().rev().enumerate() {
+        if i != 0 {
+            out.push(',');
+        }
+        let mut attributes = Reader::new(rdn);
+        let mut first = true;
+        while attributes.peek_tag().is_some() {
+            if !first {
+                out.push('+');
+            }
+            first = false;
+
+            let mut attribute = Reader::new(attributes.read(SEQUENCE)?);
+            let oid = attribute.read(OBJECT_IDENTIFIER)?;
+            let (tag, value, raw) = attribute.read_any()?;
+            match attribute_name(oid) {
+                Some(name) => {
+                    out.push_str(name);
+                    out.push('=');
+                    match decode_string(tag, value) {
+                        Some(value) => escape_value(&value, &mut out),
+                        None => push_hex(raw, &mut out),
+                    }
+                }
+                None => {
+                    out.push_str(&oid_to_string(oid)?);
+                    out.push('=');
+                    push_hex(raw, &mut out);
+                }
+            }
+        }
+    }
+
+    Some(out)
+}
+
+fn attribute_name(oid: &[u8]) -> Option<&'static str> {
+    let name = match oid {
+        b"\x55\x04\x03" => "CN",
+        b"\x55\x04\x06" => "C",
+        b"\x55\x04\x07" => "L",
+        b"\x55\x04\x08" => "ST",
+        b"\x55\x04\x09" => "STREET",
+        b"\x55\x04\x0a" => "O",
+        b"\x55\x04\x0b" => "OU",
+        b"\x09\x92\x26\x89\x93\xf2\x2c\x64\x01\x01" => "UID",
+        b"\x09\x92\x26\x89\x93\xf2\x2c\x64\x01\x19" => "DC",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn oid_to_string(oid: &[u8]) -> Option<String> {
+    let mut arcs = vec![];
+    let mut arc = 0u64;
+    for (i, &b) in oid.iter().enumerate() {
+        if arc > u64::MAX >> 7 {
+            return None;
+        }
+        arc = (arc << 7) | u64::from(b & 0x7f);
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = if arc < 80 { arc / 40 } else { 2 };
+                arcs.push(first);
+                arcs.push(arc - first * 40);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        } else if i == oid.len() - 1 {
+            return None;
+        }
+    }
+    if arcs.is_empty() {
+        return None;
+    }
+
+    let arcs = arcs.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+    Some(arcs.join("."))
+}
+
+fn decode_string(tag: u8, value: &[u8]) -> Option<String> {
+    let s = match tag {
+        UTF8_STRING | NUMERIC_STRING | PRINTABLE_STRING | IA5_STRING | VISIBLE_STRING => {
+            String::from_utf8_lossy(value).into_owned()
+        }
+        // Treated as Latin-1, as most implementations do.
+        T61_STRING => value.iter().map(|&b| b as char).collect(),
+        BMP_STRING => {
+            let units = value
+                .chunks(2)
+                .map(|c| u16::from(c[0]) << 8 | u16::from(*c.get(1).unwrap_or(&0)))
+                .collect::<Vec<_>>();
+            String::from_utf16_lossy(&units)
+        }
+        UNIVERSAL_STRING => value
+            .chunks(4)
+            .map(|c| {
+                let c = c.iter().fold(0, |c, &b| (c << 8) | u32::from(b));
+                char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER)
+            })
+            .collect(),
+        _ => return None,
+    };
+    Some(s)
+}
+
+fn escape_value(value: &str, out: &mut String) {
+    let last = value.chars().count().saturating_sub(1);
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            '"' | '+' | ',' | ';' | '<' | '>' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '#' if i == 0 => out.push_str("\\#"),
+            ' ' if i == 0 || i == last => out.push_str("\\ "),
+            '\0' => out.push_str("\\00"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn push_hex(data: &[u8], out: &mut String) {
+    out.push('#');
+    for b in data {
+        let _ = write!(out, "{:02x}", b);
+    }
+}
+
+#[test]
+fn parse_test_certificate() {
+    let keys = test_cert_gen::keys();
+    let cert = parse_certificate(keys.server.cert_and_key.cert.get_der()).unwrap();
+
+    let mut spki = Reader::new(cert.spki);
+    let mut fields = Reader::new(spki.read(SEQUENCE).unwrap());
+    assert!(spki.read_any().is_none());
+    // rsaEncryption
+    let algorithm = Reader::new(fields.read(SEQUENCE).unwrap())
+        .read(0x06)
+        .unwrap();
+    assert_eq!(algorithm, b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x01");
+    assert_eq!(fields.peek_tag(), Some(0x03));
+}
+
+#[test]
+fn test_certificate_names() {
+    let keys = test_cert_gen::keys();
+    let cert = parse_certificate(keys.server.cert_and_key.cert.get_der()).unwrap();
+
+    assert_eq!(
+        name_to_string(cert.subject).unwrap(),
+        "CN=localhost,O=ACME Service,L=Provo,ST=Utah,C=US"
+    );
+    assert_eq!(
+        name_to_string(cert.issuer).unwrap(),
+        "CN=my.ca,O=Dis,L=Sprintfield,ST=Denial,C=US"
+    );
+}
+
+#[test]
+fn subject_alt_ip_addresses() {
+    let keys = test_cert_gen::keys();
+    let cert = parse_certificate(keys.server.cert_and_key.cert.get_der()).unwrap();
+    assert!(!cert.extensions.is_empty());
+    assert_eq!(ip_addresses(cert.extensions).unwrap(), Vec::<&[u8]>::new());
+
+    // A subjectAltName of DNS:a and IP:127.0.0.1.
+    let extensions =
+        b"\x30\x12\x06\x03\x55\x1d\x11\x04\x0b\x30\x09\x82\x01a\x87\x04\x7f\x00\x00\x01";
+    assert_eq!(
+        ip_addresses(extensions).unwrap(),
+        vec![b"\x7f\x00\x00\x01" as &[u8]]
+    );
+}
+
+#[test]
+fn tls_feature_must_staple() {
+    let keys = test_cert_gen::keys();
+    let cert = parse_certificate(keys.server.cert_and_key.cert.get_der()).unwrap();
+    assert_eq!(must_staple(cert.extensions), Some(false));
+
+    // A TLS feature extension listing status_request.
+    let extensions =
+        b"\x30\x11\x06\x08\x2b\x06\x01\x05\x05\x07\x01\x18\x04\x05\x30\x03\x02\x01\x05";
+    assert_eq!(must_staple(extensions), Some(true));
+}
+
+#[test]
+fn name_escaping() {
+    // CN=" #a,b+c ", with a multi-valued RDN of O=x and an emailAddress of "e".
+    let name = b"\x31\x11\x30\x0f\x06\x03\x55\x04\x03\x0c\x08 #a,b+c \
+        \x31\x1a\x30\x08\x06\x03\x55\x04\x0a\x13\x01x\
+        \x30\x0e\x06\x09\x2a\x86\x48\x86\xf7\x0d\x01\x09\x01\x16\x01e";
+    assert_eq!(
+        name_to_string(name).unwrap(),
+        "O=x+1.2.840.113549.1.9.1=#160165,CN=\\ #a\\,b\\+c\\ "
+    );
+
+    // Invalid UTF-8 is replaced rather than rejected.
+    let name = b"\x31\x0a\x30\x08\x06\x03\x55\x04\x03\x0c\x01\xff";
+    assert_eq!(name_to_string(name).unwrap(), "CN=\u{fffd}");
+}
+
+#[test]
+fn validity_times() {
+    let time = |der: &[u8]| read_time(&mut Reader::new(der));
+
+    assert_eq!(time(b"\x17\x0d700101000000Z"), Some(0));
+    assert_eq!(time(b"\x17\x0d491231235959Z"), Some(2524607999));
+    assert_eq!(time(b"\x17\x0d500101000000Z"), Some(-631152000));
+    assert_eq!(time(b"\x18\x0f20000229120000Z"), Some(951825600));
+    assert_eq!(time(b"\x18\x0f99991231235959Z"), Some(253402300799));
+    assert_eq!(time(b"\x18\x0f16010101000000Z"), Some(-11644473600));
+
+    assert_eq!(time(b"\x17\x0d700101000000+"), None);
+    assert_eq!(time(b"\x17\x0d701301000000Z"), None);
+    assert_eq!(time(b"\x17\x0b7001010000Z"), None);
+    assert_eq!(time(b"\x18\x0d700101000000Z"), None);
+}
+
+#[test]
+fn reject_truncated() {
+    assert!(Reader::new(b"\x30\x03\x02\x01").read(SEQUENCE).is_none());
+    assert!(Reader::new(b"\x30\x81").read(SEQUENCE).is_none());
+    assert!(Reader::new(b"\x30\x85\x01\x00\x00\x00\x00")
+        .read(SEQUENCE)
+        .is_none());
+    assert!(parse_certificate(b"\x30\x00").is_none());
+}
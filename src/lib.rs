@@ -35,8 +35,8 @@
 //! # Cargo Features
 //!
 //! * `vendored` - If enabled, the crate will compile and statically link to a
-//!     vendored copy of OpenSSL. This feature has no effect on Windows and
-//!     macOS, where OpenSSL is not used.
+//!   vendored copy of OpenSSL. This feature has no effect on Windows and
+//!   macOS, where OpenSSL is not used.
 //!
 //! # Examples
 //!
@@ -99,18 +99,29 @@
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
 #[macro_use]
-#[cfg(any(target_os = "macos", target_os = "ios"))]
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "android"))]
 extern crate lazy_static;
 
 use std::any::Any;
+use std::cmp;
 use std::error;
 use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
 use std::io;
+use std::mem::MaybeUninit;
+use std::net::IpAddr;
+use std::path::Path;
 use std::result;
+use std::str;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 #[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "ios")))]
 #[macro_use]
 extern crate log;
+#[cfg(feature = "tokio")]
+extern crate tokio;
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 #[path = "imp/security_framework.rs"]
 mod imp;
@@ -121,37 +132,131 @@ mod imp;
 #[path = "imp/openssl.rs"]
 mod imp;
 
+#[cfg(feature = "tokio")]
+mod async_tls;
+mod cipher_suite;
+mod der;
+mod pem;
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+mod session_cache;
+mod split;
 #[cfg(test)]
 mod test;
 
+#[cfg(feature = "tokio")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+pub use async_tls::{AsyncTlsStream, ConnectAsync};
+pub use split::{ReadHalf, WriteHalf};
+
 /// A typedef of the result-type returned by many methods.
 pub type Result<T> = result::Result<T, Error>;
 
 /// An error returned from the TLS implementation.
-pub struct Error(imp::Error);
+pub struct Error(ErrorRepr);
+
+enum ErrorRepr {
+    Imp(imp::Error),
+    Io(io::Error),
+}
 
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        error::Error::source(&self.0)
+        match self.0 {
+            ErrorRepr::Imp(ref e) => error::Error::source(e),
+            ErrorRepr::Io(ref e) => Some(e),
+        }
     }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(&self.0, fmt)
+        match self.0 {
+            ErrorRepr::Imp(ref e) => fmt::Display::fmt(e, fmt),
+            ErrorRepr::Io(ref e) => fmt::Display::fmt(e, fmt),
+        }
     }
 }
 
 impl fmt::Debug for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Debug::fmt(&self.0, fmt)
+        match self.0 {
+            ErrorRepr::Imp(ref e) => fmt::Debug::fmt(e, fmt),
+            ErrorRepr::Io(ref e) => fmt::Debug::fmt(e, fmt),
+        }
     }
 }
 
 impl From<imp::Error> for Error {
     fn from(err: imp::Error) -> Error {
-        Error(err)
+        Error(ErrorRepr::Imp(err))
+    }
+}
+
+/// Wraps an I/O error, such as one produced by a layer built on top of this crate.
+///
+/// The resulting error has a kind of `ErrorKind::Io`, and displays as the I/O error does.
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error(ErrorRepr::Io(err))
+    }
+}
+
+impl Error {
+    /// Returns the general category of the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self.0 {
+            ErrorRepr::Imp(ref e) => e.kind(),
+            ErrorRepr::Io(_) => ErrorKind::Io,
+        }
     }
+
+    /// Returns the platform-specific error code underlying the error, if there is one.
+    ///
+    /// This is an `errno` or Windows error code for I/O errors, an `HRESULT` from SChannel, and
+    /// an `OSStatus` from Secure Transport.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match self.0 {
+            ErrorRepr::Imp(ref e) => e.raw_os_error(),
+            ErrorRepr::Io(ref e) => e.raw_os_error(),
+        }
+    }
+
+    /// Returns the description code of the TLS alert which caused a handshake to fail, if there
+    /// was one.
+    ///
+    /// This covers alerts received from the peer, and on OpenSSL also the alert sent when the
+    /// peer's certificate chain is rejected. SChannel reports only a few alerts distinguishably,
+    /// so most alerts aren't recovered there. Codes are listed in the [IANA registry].
+    ///
+    /// [IANA registry]: https://www.iana.org/assignments/tls-parameters/tls-parameters.xhtml#tls-parameters-6
+    pub fn tls_alert(&self) -> Option<u8> {
+        match self.0 {
+            ErrorRepr::Imp(ref e) => e.tls_alert(),
+            ErrorRepr::Io(_) => None,
+        }
+    }
+}
+
+/// The general categories of errors.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum ErrorKind {
+    /// A certificate in the peer's chain has expired.
+    CertExpired,
+    /// The peer's certificate chain doesn't lead to a trusted root, or was otherwise rejected.
+    CertNotTrusted,
+    /// The peer's certificate isn't valid for the requested domain.
+    HostnameMismatch,
+    /// A certificate in the peer's chain has been revoked.
+    Revoked,
+    /// The peer violated the TLS protocol, or no parameters could be negotiated with it.
+    Protocol,
+    /// An I/O error occurred on the underlying stream.
+    Io,
+    /// Any other error.
+    Other,
+    #[doc(hidden)]
+    __NonExhaustive,
 }
 
 /// A cryptographic identity.
@@ -179,17 +284,91 @@ impl Identity {
         Ok(Identity(identity))
     }
 
-    /// Parses a chain of PEM encoded X509 certificates, with the leaf certificate first.
-    /// `key` is a PEM encoded PKCS #8 formatted private key for the leaf certificate.
+    /// Parses a chain of PEM encoded X509 certificates. `key` is a PEM encoded PKCS #8 formatted
+    /// private key for the leaf certificate.
+    ///
+    /// The leaf is the certificate matching `key`, which doesn't need to come first. An error is
+    /// returned if no certificate matches it.
     ///
     /// The certificate chain should contain any intermediate cerficates that should be sent to
     /// clients to allow them to build a chain to a trusted root.
     ///
     /// A certificate chain here means a series of PEM encoded certificates concatenated together.
     pub fn from_pkcs8(pem: &[u8], key: &[u8]) -> Result<Identity> {
-        let identity = imp::Identity::from_pkcs8(pem, key)?;
+        Identity::from_pkcs8_with_options(pem, key, &ImportOptions::new())
+    }
+
+    /// Like `from_pkcs8`, but with options controlling how the private key is imported.
+    pub fn from_pkcs8_with_options(
+        pem: &[u8],
+        key: &[u8],
+        options: &ImportOptions,
+    ) -> Result<Identity> {
+        let identity = imp::Identity::from_pkcs8(pem, key, options)?;
         Ok(Identity(identity))
     }
+
+    /// Like `from_pkcs8`, but `cert` is a single DER encoded X509 certificate and `key` is a DER
+    /// encoded PKCS #8 private key.
+    pub fn from_pkcs8_der(cert: &[u8], key: &[u8]) -> Result<Identity> {
+        Identity::from_pkcs8(
+            &pem::encode("CERTIFICATE", cert),
+            &pem::encode("PRIVATE KEY", key),
+        )
+    }
+
+    /// Like `from_pkcs8`, but `key` is a PEM encoded encrypted PKCS #8 private key, which will
+    /// be decrypted with `passphrase`.
+    ///
+    /// Encrypted keys are not supported on Windows.
+    pub fn from_pkcs8_encrypted(pem: &[u8], key: &[u8], passphrase: &str) -> Result<Identity> {
+        let identity = imp::Identity::from_pkcs8_encrypted(pem, key, passphrase)?;
+        Ok(Identity(identity))
+    }
+
+    /// Serializes the identity, including its chain, into a DER-formatted PKCS #12 archive
+    /// encrypted with the specified password.
+    ///
+    /// On Windows, the identity's private key must be exportable, which is not the case for keys
+    /// imported by `Identity::from_pkcs8`. This is not supported on macOS or iOS.
+    pub fn to_pkcs12(&self, password: &str, friendly_name: Option<&str>) -> Result<Vec<u8>> {
+        Ok(self.0.to_pkcs12(password, friendly_name)?)
+    }
+
+    /// Reads a PEM encoded certificate chain and PKCS #8 private key from separate files.
+    ///
+    /// The files are parsed as in `from_pkcs8`. If a file can't be read, the returned error's
+    /// `source` is the underlying `io::Error`.
+    pub fn from_pkcs8_files(cert_path: &Path, key_path: &Path) -> Result<Identity> {
+        let pem = fs::read(cert_path)?;
+        let key = fs::read(key_path)?;
+        Identity::from_pkcs8(&pem, &key)
+    }
+}
+
+/// Options for importing an identity's private key.
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    exportable: bool,
+}
+
+impl ImportOptions {
+    /// Returns options with default settings.
+    pub fn new() -> ImportOptions {
+        ImportOptions::default()
+    }
+
+    /// Controls whether the private key can be exported again with `Identity::to_pkcs12`.
+    ///
+    /// Keys are always exportable with OpenSSL. SChannel keeps keys in their key container and
+    /// can't import them as exportable, so on Windows the import fails if this is set.
+    ///
+    /// Defaults to `false`.
+    pub fn exportable(&mut self, exportable: bool) -> &mut ImportOptions {
+        self.exportable = exportable;
+        self
+    }
 }
 
 /// An X509 certificate.
@@ -209,15 +388,217 @@ impl Certificate {
         Ok(Certificate(cert))
     }
 
+    /// Parses every certificate in a bundle of PEM blocks, such as a CA bundle.
+    ///
+    /// Blocks other than certificates, such as private keys, are skipped.
+    pub fn from_pem_bundle(bundle: &[u8]) -> Result<Vec<Certificate>> {
+        let mut certs = vec![];
+        for block in pem::PemBlock::new(bundle)? {
+            if block.starts_with(b"-----BEGIN CERTIFICATE-----") {
+                certs.push(Certificate::from_pem(block)?);
+            }
+        }
+        Ok(certs)
+    }
+
     /// Returns the DER-encoded representation of this certificate.
     pub fn to_der(&self) -> Result<Vec<u8>> {
         let der = self.0.to_der()?;
         Ok(der)
     }
+
+    /// Returns the PEM-encoded representation of this certificate.
+    ///
+    /// Lines are wrapped at 64 columns and terminated with `\n`.
+    pub fn to_pem(&self) -> Result<Vec<u8>> {
+        Ok(pem::encode("CERTIFICATE", &self.to_der()?))
+    }
+
+    /// Returns the certificate's subject as an [RFC 4514] string.
+    ///
+    /// Values which aren't valid in their string type are converted lossily.
+    ///
+    /// [RFC 4514]: https://tools.ietf.org/html/rfc4514
+    pub fn subject_name(&self) -> Result<String> {
+        Ok(self.0.subject_name()?)
+    }
+
+    /// Returns the certificate's issuer as an [RFC 4514] string.
+    ///
+    /// Values which aren't valid in their string type are converted lossily.
+    ///
+    /// [RFC 4514]: https://tools.ietf.org/html/rfc4514
+    pub fn issuer_name(&self) -> Result<String> {
+        Ok(self.0.issuer_name()?)
+    }
+
+    /// Returns the start of the certificate's validity period, in seconds since the Unix epoch.
+    pub fn not_before(&self) -> Result<i64> {
+        Ok(self.0.not_before()?)
+    }
+
+    /// Returns the end of the certificate's validity period, in seconds since the Unix epoch.
+    pub fn not_after(&self) -> Result<i64> {
+        Ok(self.0.not_after()?)
+    }
+
+    /// Returns the certificate's serial number as unsigned big-endian bytes.
+    pub fn serial_number(&self) -> Result<Vec<u8>> {
+        Ok(self.0.serial_number()?)
+    }
+
+    /// Returns a hash of the DER-encoded representation of this certificate.
+    pub fn fingerprint(&self, algo: HashAlgo) -> Result<Vec<u8>> {
+        Ok(self.0.fingerprint(algo)?)
+    }
+
+    /// Returns the subject alternative names of this certificate.
+    ///
+    /// Names of types other than those in `SubjectAltName` are skipped. An empty list is returned
+    /// if the certificate has no subject alternative name extension.
+    pub fn subject_alt_names(&self) -> Result<Vec<SubjectAltName>> {
+        Ok(self.0.subject_alt_names()?)
+    }
+
+    /// Returns whether this certificate requires its server to staple an OCSP response, which is
+    /// known as OCSP must-staple.
+    ///
+    /// This is signalled by a TLS feature extension listing `status_request`, as described in
+    /// [RFC 7633].
+    ///
+    /// [RFC 7633]: https://tools.ietf.org/html/rfc7633
+    pub fn has_must_staple(&self) -> Result<bool> {
+        Ok(self.0.has_must_staple()?)
+    }
+
+    /// Returns the algorithm and size of this certificate's public key.
+    ///
+    /// Returns an error if the key uses an algorithm or curve not represented by `PublicKeyInfo`.
+    pub fn public_key_info(&self) -> Result<PublicKeyInfo> {
+        Ok(self.0.public_key_info()?)
+    }
+}
+
+// Certificates are compared by their DER encoding, as SChannel compares `CertContext`s.
+impl PartialEq for Certificate {
+    fn eq(&self, other: &Certificate) -> bool {
+        match (self.to_der(), other.to_der()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Certificate {}
+
+impl Hash for Certificate {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_der().ok().hash(state);
+    }
+}
+
+/// Hash algorithms.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum HashAlgo {
+    /// SHA-1.
+    ///
+    /// # Warning
+    ///
+    /// SHA-1 is not collision resistant, and should not be relied on to identify certificates.
+    Sha1,
+    /// SHA-256.
+    Sha256,
+    /// SHA-384.
+    Sha384,
+    /// SHA-512.
+    Sha512,
+    #[doc(hidden)]
+    __NonExhaustive,
+}
+
+/// A subject alternative name of a certificate.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SubjectAltName {
+    /// A DNS name.
+    DnsName(String),
+    /// An IP address.
+    IpAddress(IpAddr),
+    /// An email address.
+    Email(String),
+    /// A URI.
+    Uri(String),
+}
+
+/// The algorithm and size of a certificate's public key.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PublicKeyInfo {
+    /// An RSA key.
+    Rsa {
+        /// The size of the modulus in bits.
+        bits: u32,
+    },
+    /// An elliptic curve key.
+    Ecdsa {
+        /// The curve the key is on.
+        curve: NamedGroup,
+    },
+    /// An Ed25519 key.
+    Ed25519,
+    /// An Ed448 key.
+    Ed448,
+}
+
+/// The certificate chain presented by a server, passed to a verify callback.
+pub struct CertificateChain {
+    certificates: Vec<Certificate>,
+    verified: bool,
+}
+
+impl CertificateChain {
+    /// Returns the certificates in the chain, starting with the server's leaf certificate.
+    pub fn certificates(&self) -> &[Certificate] {
+        &self.certificates
+    }
+
+    /// Returns the server's leaf certificate.
+    pub fn leaf(&self) -> Option<&Certificate> {
+        self.certificates.first()
+    }
+
+    /// Determines if the chain passed the implementation's built-in validation.
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+}
+
+/// Validates a certificate chain without performing a handshake.
+///
+/// The chain starts with the leaf certificate, followed by any intermediates needed to reach one
+/// of `roots`. Only `roots` are trusted, and the system's trusted roots aren't consulted. If
+/// `hostname` is provided, the leaf must be valid for it as a TLS server. The chain is validated
+/// as of `when`, or the current time if it's `None`. Revocation is not checked.
+///
+/// Secure Transport is unable to validate a chain as of another time, so an error is returned if
+/// `when` is provided on macOS and iOS.
+pub fn verify_certificate_chain(
+    chain: &[Certificate],
+    roots: &[Certificate],
+    hostname: Option<&str>,
+    when: Option<SystemTime>,
+) -> Result<()> {
+    if chain.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the chain must contain at least a leaf certificate",
+        )
+        .into());
+    }
+    Ok(imp::verify_certificate_chain(chain, roots, hostname, when)?)
 }
 
 /// A TLS stream which has been interrupted midway through the handshake process.
-pub struct MidHandshakeTlsStream<S>(imp::MidHandshakeTlsStream<S>);
+pub struct MidHandshakeTlsStream<S>(imp::MidHandshakeTlsStream<StreamSlot<S>>, bool);
 
 impl<S> fmt::Debug for MidHandshakeTlsStream<S>
 where
@@ -231,12 +612,41 @@ where
 impl<S> MidHandshakeTlsStream<S> {
     /// Returns a shared reference to the inner stream.
     pub fn get_ref(&self) -> &S {
-        self.0.get_ref()
+        self.0.get_ref().get_ref()
     }
 
     /// Returns a mutable reference to the inner stream.
     pub fn get_mut(&mut self) -> &mut S {
-        self.0.get_mut()
+        self.0.get_mut().get_mut()
+    }
+
+    /// Returns `true` if the handshake was interrupted because reading from the inner stream
+    /// would have blocked.
+    ///
+    /// The handshake should be resumed once the stream is readable.
+    pub fn wants_read(&self) -> bool {
+        !self.0.get_ref().write_blocked()
+    }
+
+    /// Returns `true` if the handshake was interrupted because writing to or flushing the inner
+    /// stream would have blocked.
+    ///
+    /// The handshake should be resumed once the stream is writable.
+    pub fn wants_write(&self) -> bool {
+        self.0.get_ref().write_blocked()
+    }
+
+    /// Returns the names of the CAs the server listed in its certificate request, as [RFC 4514]
+    /// strings.
+    ///
+    /// A client whose handshake was interrupted can use these to pick an identity for a
+    /// connector before resuming it. The list is empty until the request has been received, if
+    /// the server didn't name any CAs, and on the server side. Only the OpenSSL backend supports
+    /// it; other backends return an error.
+    ///
+    /// [RFC 4514]: https://tools.ietf.org/html/rfc4514
+    pub fn requested_client_cas(&self) -> Result<Vec<String>> {
+        Ok(self.0.requested_client_cas()?)
     }
 }
 
@@ -252,9 +662,10 @@ where
     /// stream is an asynchronous one then `HandshakeError::WouldBlock` may
     /// just mean to wait for more I/O to happen later.
     pub fn handshake(self) -> result::Result<TlsStream<S>, HandshakeError<S>> {
+        let flush_after_write = self.1;
         match self.0.handshake() {
-            Ok(s) => Ok(TlsStream(s)),
-            Err(e) => Err(e.into()),
+            Ok(s) => Ok(TlsStream(s, StreamState::new(flush_after_write))),
+            Err(e) => Err(HandshakeError::from(e).flush_after_write(flush_after_write)),
         }
     }
 }
@@ -263,7 +674,16 @@ where
 #[derive(Debug)]
 pub enum HandshakeError<S> {
     /// A fatal error.
-    Failure(Error),
+    ///
+    /// The underlying stream is returned where it can be recovered, so that it can be inspected
+    /// or reused. That's the case for handshakes which time out, and for any failed handshake
+    /// with the OpenSSL backend.
+    Failure {
+        /// The error which caused the handshake to fail.
+        error: Error,
+        /// The underlying stream.
+        stream: Option<S>,
+    },
 
     /// A stream interrupted midway through the handshake process due to a
     /// `WouldBlock` error.
@@ -280,7 +700,7 @@ where
 {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
-            HandshakeError::Failure(ref e) => Some(e),
+            HandshakeError::Failure { ref error, .. } => Some(error),
             HandshakeError::WouldBlock(_) => None,
         }
     }
@@ -292,25 +712,54 @@ where
 {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            HandshakeError::Failure(ref e) => fmt::Display::fmt(e, fmt),
+            HandshakeError::Failure { ref error, .. } => fmt::Display::fmt(error, fmt),
             HandshakeError::WouldBlock(_) => fmt.write_str("the handshake process was interrupted"),
         }
     }
 }
 
-impl<S> From<imp::HandshakeError<S>> for HandshakeError<S> {
-    fn from(e: imp::HandshakeError<S>) -> HandshakeError<S> {
+impl<S> From<imp::HandshakeError<StreamSlot<S>>> for HandshakeError<S> {
+    fn from(e: imp::HandshakeError<StreamSlot<S>>) -> HandshakeError<S> {
         match e {
-            imp::HandshakeError::Failure(e) => HandshakeError::Failure(Error(e)),
+            imp::HandshakeError::Failure(e) => HandshakeError::Failure {
+                error: e.into(),
+                stream: None,
+            },
+            #[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "ios")))]
+            imp::HandshakeError::FailureWithStream(mut s) => {
+                let stream = s.get_mut().stream.take();
+                HandshakeError::Failure {
+                    error: s.into_error().into(),
+                    stream,
+                }
+            }
+            #[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "ios")))]
+            imp::HandshakeError::FailureAfterHandshake(e, mut s) => HandshakeError::Failure {
+                error: e.into(),
+                stream: s.get_mut().stream.take(),
+            },
             imp::HandshakeError::WouldBlock(s) => {
-                HandshakeError::WouldBlock(MidHandshakeTlsStream(s))
+                HandshakeError::WouldBlock(MidHandshakeTlsStream(s, false))
+            }
+        }
+    }
+}
+
+impl<S> HandshakeError<S> {
+    // Carries the connector's write behavior over to the stream once the handshake is resumed.
+    fn flush_after_write(self, flush_after_write: bool) -> HandshakeError<S> {
+        match self {
+            HandshakeError::WouldBlock(s) => {
+                HandshakeError::WouldBlock(MidHandshakeTlsStream(s.0, flush_after_write))
             }
+            e => e,
         }
     }
 }
 
 /// SSL/TLS protocol versions.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
 pub enum Protocol {
     /// The SSL 3.0 protocol.
     ///
@@ -325,22 +774,263 @@ pub enum Protocol {
     Tlsv11,
     /// The TLS 1.2 protocol.
     Tlsv12,
+    /// The TLS 1.3 protocol.
+    ///
+    /// Not every platform supports TLS 1.3. Where it is unavailable, requiring it as the minimum
+    /// protocol version will cause handshakes to fail.
+    Tlsv13,
+    #[doc(hidden)]
+    __NonExhaustive,
+}
+
+/// A step of a client's handshake, reported to `TlsConnectorBuilder::on_handshake_event`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum HandshakeEvent {
+    /// The ClientHello message was sent.
+    ///
+    /// This is reported again if the server asks for a new ClientHello with a
+    /// HelloRetryRequest.
+    ClientHelloSent,
+    /// The ServerHello message was received.
+    ///
+    /// A HelloRetryRequest is also reported as this, since it's sent as a ServerHello message.
+    ServerHelloReceived,
+    /// The server's Certificate message was received.
+    ///
+    /// This isn't reported for resumed sessions, where no certificate is sent.
+    CertificateReceived,
+    /// The handshake completed.
+    Finished,
     #[doc(hidden)]
     __NonExhaustive,
 }
 
+/// Certificate revocation checks performed while validating the server's certificate chain.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RevocationMode {
+    /// Revocation is not checked.
+    Disabled,
+    /// Only the revocation status of the leaf certificate is checked.
+    EndEntityOnly,
+    /// The revocation status of every certificate in the chain is checked.
+    EntireChain,
+}
+
+/// A certificate validation error which can be ignored by a `TlsConnector`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ValidationError {
+    /// A certificate in the chain has expired.
+    Expired,
+    /// The chain doesn't lead to a trusted root certificate.
+    UntrustedRoot,
+    /// The certificate doesn't match the domain being connected to.
+    HostnameMismatch,
+    /// A certificate in the chain has been revoked.
+    Revoked,
+    /// The certificate isn't valid for authenticating a server.
+    WrongUsage,
+}
+
+/// Whether a `TlsAcceptor` requests a certificate from the client.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ClientCertMode {
+    /// No certificate is requested.
+    None,
+    /// A certificate is requested, and verified if the client presents one.
+    Optional,
+    /// A certificate is requested and verified, and the handshake fails if the client doesn't
+    /// present one.
+    Required,
+}
+
+/// A Windows system certificate store of the current user.
+#[cfg(target_os = "windows")]
+#[cfg_attr(docsrs, doc(cfg(target_os = "windows")))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SystemStore {
+    /// The trusted root certification authorities (`Root`).
+    Root,
+    /// The intermediate certification authorities (`CA`).
+    Ca,
+    /// The personal certificates (`My`).
+    My,
+    /// The trusted people (`TrustedPeople`).
+    TrustedPeople,
+}
+
+/// The maximum amount of plaintext in each TLS record, as negotiated by the max_fragment_length
+/// extension of [RFC 6066].
+///
+/// [RFC 6066]: https://tools.ietf.org/html/rfc6066#section-4
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MaxFragmentLength {
+    /// 512 bytes.
+    Bytes512,
+    /// 1024 bytes.
+    Bytes1024,
+    /// 2048 bytes.
+    Bytes2048,
+    /// 4096 bytes.
+    Bytes4096,
+}
+
+/// A signature scheme used in the TLS handshake, as named by [RFC 8446].
+///
+/// [RFC 8446]: https://tools.ietf.org/html/rfc8446#section-4.2.3
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SignatureScheme {
+    /// RSASSA-PKCS1-v1_5 with SHA-256.
+    RsaPkcs1Sha256,
+    /// RSASSA-PKCS1-v1_5 with SHA-384.
+    RsaPkcs1Sha384,
+    /// RSASSA-PKCS1-v1_5 with SHA-512.
+    RsaPkcs1Sha512,
+    /// RSASSA-PSS with SHA-256, using an `rsaEncryption` key.
+    RsaPssRsaeSha256,
+    /// RSASSA-PSS with SHA-384, using an `rsaEncryption` key.
+    RsaPssRsaeSha384,
+    /// RSASSA-PSS with SHA-512, using an `rsaEncryption` key.
+    RsaPssRsaeSha512,
+    /// ECDSA on the P-256 curve with SHA-256.
+    EcdsaSecp256r1Sha256,
+    /// ECDSA on the P-384 curve with SHA-384.
+    EcdsaSecp384r1Sha384,
+    /// ECDSA on the P-521 curve with SHA-512.
+    EcdsaSecp521r1Sha512,
+    /// EdDSA on Curve25519.
+    Ed25519,
+}
+
+/// A group used for key exchange in the TLS handshake, as named by [RFC 8446].
+///
+/// [RFC 8446]: https://tools.ietf.org/html/rfc8446#section-4.2.7
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum NamedGroup {
+    /// The P-256 curve.
+    Secp256r1,
+    /// The P-384 curve.
+    Secp384r1,
+    /// The P-521 curve.
+    Secp521r1,
+    /// X25519.
+    X25519,
+    /// X448.
+    X448,
+}
+
+/// A TLS cipher suite.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CipherSuite {
+    id: u16,
+    name: String,
+}
+
+impl CipherSuite {
+    /// Returns the cipher suite with the specified IANA identifier.
+    pub fn from_id(id: u16) -> CipherSuite {
+        let name = match cipher_suite::name(id) {
+            Some(name) => name.to_owned(),
+            None => format!("0x{:04X}", id),
+        };
+        CipherSuite { id, name }
+    }
+
+    /// Returns the cipher suite with the specified IANA name, if it is known.
+    pub fn from_name(name: &str) -> Option<CipherSuite> {
+        cipher_suite::id(name).map(|id| CipherSuite {
+            id,
+            name: name.to_owned(),
+        })
+    }
+
+    /// Returns the IANA name of the cipher suite, for example `TLS_AES_128_GCM_SHA256`.
+    ///
+    /// Suites the implementation can't name are identified by their hexadecimal value, for
+    /// example `0x1301`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the 16 bit IANA identifier of the cipher suite.
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+}
+
+/// Returns the cipher suites supported by the platform's TLS implementation.
+///
+/// These are the suites which `TlsConnectorBuilder::ciphers` and `TlsAcceptorBuilder::ciphers`
+/// can enable. On Windows, suites without a known IANA name are omitted. The OpenSSL backend
+/// requires OpenSSL 1.1.1 or newer to report any suites.
+pub fn supported_cipher_suites() -> Result<Vec<CipherSuite>> {
+    Ok(imp::supported_cipher_suites()?)
+}
+
+type KeyLogCallback = Arc<dyn Fn(&str) + Send + Sync>;
+
+type VerifyCallback = Arc<dyn Fn(&CertificateChain) -> Result<()> + Send + Sync>;
+
+type VerificationFailureCallback = Arc<dyn Fn(&[Certificate]) + Send + Sync>;
+
+type HandshakeEventCallback = Arc<dyn Fn(HandshakeEvent) + Send + Sync>;
+
+type ClientCertVerifyCallback = Arc<dyn Fn(&[Certificate]) -> Result<()> + Send + Sync>;
+
 /// A builder for `TlsConnector`s.
 pub struct TlsConnectorBuilder {
     identity: Option<Identity>,
+    client_identities: Vec<Identity>,
     min_protocol: Option<Protocol>,
     max_protocol: Option<Protocol>,
     root_certificates: Vec<Certificate>,
+    #[cfg(target_os = "windows")]
+    root_store: Option<imp::CertStore>,
+    #[cfg(target_os = "windows")]
+    system_stores: Vec<SystemStore>,
     accept_invalid_certs: bool,
     accept_invalid_hostnames: bool,
     use_sni: bool,
+    server_name_indication: Option<String>,
     disable_built_in_roots: bool,
+    ciphers: Vec<CipherSuite>,
+    signature_algorithms: Vec<SignatureScheme>,
+    elliptic_curves: Vec<NamedGroup>,
+    pinned_public_keys: Vec<[u8; 32]>,
+    pinned_certificates: Vec<[u8; 32]>,
+    #[cfg_attr(not(have_ossl111), allow(dead_code))]
+    key_log_callback: Option<KeyLogCallback>,
+    verify_callback: Option<VerifyCallback>,
+    verification_failure_callback: Option<VerificationFailureCallback>,
+    revocation_mode: Option<RevocationMode>,
+    handshake_timeout: Option<Duration>,
+    flush_after_write: bool,
+    #[cfg_attr(
+        any(target_os = "macos", target_os = "ios", target_os = "windows"),
+        allow(dead_code)
+    )]
+    allow_unknown_revocation: bool,
+    ignored_validation_errors: Vec<ValidationError>,
+    require_stapled_ocsp: bool,
+    #[cfg_attr(any(target_os = "macos", target_os = "ios"), allow(dead_code))]
+    session_tickets: bool,
+    #[cfg_attr(any(target_os = "macos", target_os = "ios"), allow(dead_code))]
+    session_cache_capacity: usize,
+    #[cfg_attr(any(target_os = "macos", target_os = "ios"), allow(dead_code))]
+    credential_caching: bool,
+    max_chain_depth: Option<usize>,
+    max_fragment_length: Option<MaxFragmentLength>,
+    false_start: bool,
+    require_extended_master_secret: bool,
+    handshake_event_callback: Option<HandshakeEventCallback>,
+    #[cfg_attr(not(target_os = "android"), allow(dead_code))]
+    refresh_system_roots: bool,
+    #[cfg(feature = "psk")]
+    psk: Option<(Vec<u8>, Vec<u8>)>,
     #[cfg(feature = "alpn")]
     alpn: Vec<String>,
+    #[cfg(feature = "alpn")]
+    require_alpn: bool,
 }
 
 impl TlsConnectorBuilder {
@@ -350,6 +1040,21 @@ impl TlsConnectorBuilder {
         self
     }
 
+    /// Adds an identity which may be used for client certificate authentication.
+    ///
+    /// When the server requests a certificate, the first added identity whose certificate or
+    /// chain was issued by one of the certificate authorities the server names is used. If none
+    /// match, the identity set by `identity` is used, if any.
+    ///
+    /// Only the OpenSSL backend supports this: building the connector fails before OpenSSL
+    /// 1.1.1, and other backends return an error from `connect` if any identities are added.
+    ///
+    /// Defaults to no identities.
+    pub fn add_client_identity(&mut self, identity: Identity) -> &mut TlsConnectorBuilder {
+        self.client_identities.push(identity);
+        self
+    }
+
     /// Sets the minimum supported protocol version.
     ///
     /// A value of `None` enables support for the oldest protocols supported by the implementation.
@@ -381,6 +1086,45 @@ impl TlsConnectorBuilder {
         self
     }
 
+    /// Sets the certificate store whose certificates the connector will trust as roots.
+    ///
+    /// The store is used directly rather than copied, which avoids importing its certificates
+    /// again for each `TlsConnector`. Certificates passed to `add_root_certificate` are added to
+    /// it when the connector is built.
+    ///
+    /// Defaults to a new in-memory store.
+    #[cfg(target_os = "windows")]
+    #[cfg_attr(docsrs, doc(cfg(target_os = "windows")))]
+    pub fn set_root_store(&mut self, store: imp::CertStore) -> &mut TlsConnectorBuilder {
+        self.root_store = Some(store);
+        self
+    }
+
+    /// Adds the certificates of one of the current user's system stores to the set of roots
+    /// that the connector will trust.
+    ///
+    /// The current user's `Root`, `CA` and `TrustedPeople` stores include those of the local
+    /// machine, so this picks up certificates installed by enterprise policy, such as
+    /// intermediates in the `CA` store. The store is read when the connector is built, and its
+    /// certificates are added to the root store like those passed to `add_root_certificate`.
+    ///
+    /// Defaults to no system stores.
+    #[cfg(target_os = "windows")]
+    #[cfg_attr(docsrs, doc(cfg(target_os = "windows")))]
+    pub fn add_roots_from_system_store(&mut self, store: SystemStore) -> &mut TlsConnectorBuilder {
+        self.system_stores.push(store);
+        self
+    }
+
+    /// Adds each certificate in a PEM bundle to the set of roots that the connector will trust.
+    ///
+    /// Blocks other than certificates, such as private keys, are skipped.
+    pub fn add_root_certificates_pem(&mut self, bundle: &[u8]) -> Result<&mut TlsConnectorBuilder> {
+        self.root_certificates
+            .extend(Certificate::from_pem_bundle(bundle)?);
+        Ok(self)
+    }
+
     /// Controls the use of built-in system certificates during certificate validation.
     ///
     /// Defaults to `false` -- built-in system certs will be used.
@@ -389,67 +1133,451 @@ impl TlsConnectorBuilder {
         self
     }
 
-    /// Request specific protocols through ALPN (Application-Layer Protocol Negotiation).
+    /// Restricts the cipher suites which may be negotiated.
     ///
-    /// Defaults to no protocols.
-    #[cfg(feature = "alpn")]
-    #[cfg_attr(docsrs, doc(cfg(feature = "alpn")))]
-    pub fn request_alpns(&mut self, protocols: &[&str]) -> &mut TlsConnectorBuilder {
-        self.alpn = protocols.iter().map(|s| (*s).to_owned()).collect();
+    /// Suites which the implementation doesn't recognize are silently skipped, so the handshake
+    /// will fail if none of them are recognized. On Windows, suites can only be restricted by
+    /// their key exchange and bulk cipher, and the OpenSSL backend requires OpenSSL 1.1.1 or newer.
+    ///
+    /// Defaults to an empty list, which uses the platform's default suites.
+    pub fn ciphers(&mut self, suites: &[CipherSuite]) -> &mut TlsConnectorBuilder {
+        self.ciphers = suites.to_vec();
         self
     }
 
-    /// Controls the use of certificate validation.
+    /// Restricts the signature schemes offered in the signature_algorithms extension.
     ///
-    /// Defaults to `false`.
+    /// Schemes which the implementation doesn't support are skipped, and `connect` returns an
+    /// error if none of them are supported. On Windows, schemes can only be restricted by their
+    /// signature algorithm and hash, so RSA PKCS#1 and PSS schemes are either both allowed or both
+    /// disallowed. The OpenSSL backend requires OpenSSL 1.1.1 or newer, and Secure Transport
+    /// doesn't support any of them.
     ///
-    /// # Warning
+    /// Defaults to an empty list, which uses the platform's default schemes.
+    pub fn signature_algorithms(&mut self, algs: &[SignatureScheme]) -> &mut TlsConnectorBuilder {
+        self.signature_algorithms = algs.to_vec();
+        self
+    }
+
+    /// Restricts the groups offered in the supported_groups extension for key exchange.
     ///
-    /// You should think very carefully before using this method. If invalid certificates are trusted, *any*
-    /// certificate for *any* site will be trusted for use. This includes expired certificates. This introduces
-    /// significant vulnerabilities, and should only be used as a last resort.
-    pub fn danger_accept_invalid_certs(
-        &mut self,
-        accept_invalid_certs: bool,
-    ) -> &mut TlsConnectorBuilder {
-        self.accept_invalid_certs = accept_invalid_certs;
+    /// This is only supported by the OpenSSL backend, starting with OpenSSL 1.1.1; other backends
+    /// return an error from `connect` if any groups are set.
+    ///
+    /// Defaults to an empty list, which uses the platform's default groups.
+    pub fn elliptic_curves(&mut self, groups: &[NamedGroup]) -> &mut TlsConnectorBuilder {
+        self.elliptic_curves = groups.to_vec();
         self
     }
 
-    /// Controls the use of Server Name Indication (SNI).
+    /// Adds a pin on the SHA-256 hash of a DER-encoded SubjectPublicKeyInfo.
     ///
-    /// Defaults to `true`.
-    pub fn use_sni(&mut self, use_sni: bool) -> &mut TlsConnectorBuilder {
-        self.use_sni = use_sni;
+    /// If any pins are added, the handshake will fail unless the public key of the server's leaf
+    /// certificate matches one of them, or the leaf certificate matches a pin added with
+    /// `add_pinned_certificate_sha256`. Pins are checked in addition to normal certificate
+    /// validation, and are still enforced when invalid certificates are accepted.
+    ///
+    /// Defaults to no pins.
+    pub fn add_pinned_public_key_sha256(&mut self, hash: [u8; 32]) -> &mut TlsConnectorBuilder {
+        self.pinned_public_keys.push(hash);
         self
     }
 
-    /// Controls the use of hostname verification.
+    /// Adds a pin on the SHA-256 hash of a whole DER-encoded certificate.
     ///
-    /// Defaults to `false`.
+    /// This behaves like `add_pinned_public_key_sha256`, and the two kinds of pins are combined: the
+    /// server's leaf certificate is accepted if it matches any pin of either kind. Unlike a public
+    /// key pin, a certificate pin stops matching when the server's certificate is reissued, even
+    /// with the same key.
     ///
-    /// # Warning
+    /// Defaults to no pins.
+    pub fn add_pinned_certificate_sha256(&mut self, hash: [u8; 32]) -> &mut TlsConnectorBuilder {
+        self.pinned_certificates.push(hash);
+        self
+    }
+
+    /// Sets a callback which is passed the session's secrets as lines in the NSS key log format.
     ///
-    /// You should think very carefully before using this method. If invalid hostnames are trusted, *any* valid
-    /// certificate for *any* site will be trusted for use. This introduces significant vulnerabilities, and should
-    /// only be used as a last resort.
-    pub fn danger_accept_invalid_hostnames(
+    /// If no callback is set, the lines are instead appended to the file named by the
+    /// `SSLKEYLOGFILE` environment variable, if it is set.
+    ///
+    /// This is intended for debugging only, since anyone with the logged secrets can decrypt the
+    /// session's traffic. It is currently only supported by the OpenSSL backend, with OpenSSL
+    /// 1.1.1 or newer.
+    ///
+    /// Defaults to no callback.
+    pub fn key_log_callback(
         &mut self,
-        accept_invalid_hostnames: bool,
+        f: impl Fn(&str) + Send + Sync + 'static,
     ) -> &mut TlsConnectorBuilder {
-        self.accept_invalid_hostnames = accept_invalid_hostnames;
+        self.key_log_callback = Some(Arc::new(f));
         self
     }
 
-    /// Creates a new `TlsConnector`.
-    pub fn build(&self) -> Result<TlsConnector> {
-        let connector = imp::TlsConnector::new(self)?;
-        Ok(TlsConnector(connector))
+    /// Sets a callback which makes the final decision on whether to trust the server's
+    /// certificate chain.
+    ///
+    /// The callback runs after the implementation's built-in validation, which uses the
+    /// configured roots, honors `disable_built_in_roots` and checks the hostname unless
+    /// `danger_accept_invalid_hostnames` is set. The outcome of that validation is reported by
+    /// `CertificateChain::is_verified`. Returning `Ok(())` accepts the chain even if it failed
+    /// validation, and returning an error aborts the handshake.
+    ///
+    /// Pins are checked before the callback runs and can't be overridden by it. When a callback
+    /// is set, `danger_accept_invalid_certs` has no effect.
+    ///
+    /// Defaults to no callback.
+    pub fn verify_callback(
+        &mut self,
+        f: impl Fn(&CertificateChain) -> Result<()> + Send + Sync + 'static,
+    ) -> &mut TlsConnectorBuilder {
+        self.verify_callback = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets a callback which is passed the server's certificate chain when it's rejected.
+    ///
+    /// The callback runs before the handshake error is returned, whether the chain was rejected
+    /// by the implementation's validation, a pin or the verify callback. It can't change the
+    /// outcome of the handshake, and is intended for logging what a misconfigured server actually
+    /// presented.
+    ///
+    /// Defaults to no callback.
+    pub fn on_verification_failure(
+        &mut self,
+        f: impl Fn(&[Certificate]) + Send + Sync + 'static,
+    ) -> &mut TlsConnectorBuilder {
+        self.verification_failure_callback = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets the revocation checks performed on the server's certificate chain.
+    ///
+    /// With OpenSSL, revocation is checked against the CRLs available to the certificate store.
+    /// SChannel and Secure Transport don't allow these checks to be configured, and return an
+    /// error from `connect` if a mode is set.
+    ///
+    /// Defaults to the platform's behavior, which for OpenSSL is `RevocationMode::Disabled`.
+    pub fn revocation_checking(&mut self, mode: RevocationMode) -> &mut TlsConnectorBuilder {
+        self.revocation_mode = Some(mode);
+        self
+    }
+
+    /// Controls whether certificates are accepted when their revocation status can't be
+    /// determined, such as when no CRL is available for them.
+    ///
+    /// Defaults to `false`.
+    pub fn allow_unknown_revocation(&mut self, allow: bool) -> &mut TlsConnectorBuilder {
+        self.allow_unknown_revocation = allow;
+        self
+    }
+
+    /// Accepts certificate chains whose only validation errors are among the specified ones.
+    ///
+    /// Unlike `danger_accept_invalid_certs`, chains with any other error are still rejected. On
+    /// Windows, SChannel only reports the most serious error in the chain, so ignoring it may hide
+    /// others. Secure Transport doesn't report individual errors, and returns an error from
+    /// `connect` if any are set.
+    ///
+    /// Defaults to an empty list.
+    pub fn ignore_validation_errors(
+        &mut self,
+        errors: &[ValidationError],
+    ) -> &mut TlsConnectorBuilder {
+        self.ignored_validation_errors = errors.to_vec();
+        self
+    }
+
+    /// Controls whether the handshake fails if the server doesn't staple an OCSP response.
+    ///
+    /// The response itself is not validated; it can be retrieved with `TlsStream::ocsp_response`.
+    /// This is only supported by the OpenSSL backend; other backends return an error from
+    /// `connect` if it is enabled.
+    ///
+    /// Regardless of this setting, the OpenSSL backend requires a response from servers whose
+    /// certificate has OCSP must-staple (see `Certificate::has_must_staple`), unless invalid
+    /// certificates are accepted. Other backends can't tell whether a response was stapled, so
+    /// they don't enforce it.
+    ///
+    /// Defaults to `false`.
+    pub fn require_stapled_ocsp(&mut self, require: bool) -> &mut TlsConnectorBuilder {
+        self.require_stapled_ocsp = require;
+        self
+    }
+
+    /// Enables caching of sessions, so that later connections to the same domain can resume them
+    /// with an abbreviated handshake.
+    ///
+    /// The cache is shared by clones of the `TlsConnector`. With OpenSSL, only sessions of
+    /// connections which were closed with `TlsStream::shutdown` can be resumed. It is not
+    /// supported by the Secure Transport backend.
+    ///
+    /// Defaults to `false`.
+    pub fn session_tickets(&mut self, enabled: bool) -> &mut TlsConnectorBuilder {
+        self.session_tickets = enabled;
+        self
+    }
+
+    /// Sets the maximum number of domains whose sessions are cached.
+    ///
+    /// Once the cache is full, the session of the least recently used domain is evicted.
+    ///
+    /// Defaults to 64.
+    pub fn session_cache_capacity(&mut self, capacity: usize) -> &mut TlsConnectorBuilder {
+        self.session_cache_capacity = capacity;
+        self
+    }
+
+    /// Controls whether credentials and sessions are reused across connections.
+    ///
+    /// When disabled, every connection acquires fresh credentials and performs a full handshake,
+    /// even if `session_tickets` is enabled. This ensures a rotated server or client certificate
+    /// is picked up by the next connection, at the cost of a slower handshake for each of them.
+    ///
+    /// Defaults to `true`.
+    pub fn credential_caching(&mut self, enabled: bool) -> &mut TlsConnectorBuilder {
+        self.credential_caching = enabled;
+        self
+    }
+
+    /// Sets the maximum number of intermediate certificates allowed between the server's
+    /// certificate and the trust anchor.
+    ///
+    /// A depth of 0 only accepts certificates issued directly by a trusted root, or self-signed
+    /// certificates which are themselves trusted.
+    ///
+    /// Defaults to no limit.
+    pub fn max_chain_depth(&mut self, depth: usize) -> &mut TlsConnectorBuilder {
+        self.max_chain_depth = Some(depth);
+        self
+    }
+
+    /// Requests that the server send records no larger than the specified length.
+    ///
+    /// Smaller records let the peer start processing data sooner, at the cost of throughput. This
+    /// is only supported by the OpenSSL backend, starting with OpenSSL 1.1.1; other backends
+    /// return an error from `connect` if a length is set.
+    ///
+    /// Defaults to `None`, which leaves the record size to the implementation.
+    pub fn max_fragment_length(
+        &mut self,
+        len: Option<MaxFragmentLength>,
+    ) -> &mut TlsConnectorBuilder {
+        self.max_fragment_length = len;
+        self
+    }
+
+    /// Enables TLS False Start, which sends application data before the server's Finished
+    /// message has been received.
+    ///
+    /// This saves a round trip on full handshakes, but the first data is sent before the
+    /// handshake has been authenticated. It's only safe with forward-secret cipher suites, and
+    /// when the protocol to speak is already fixed, for example with ALPN. None of the backends
+    /// currently support it: OpenSSL fails to build the connector, and other backends return an
+    /// error from `connect` if it's enabled.
+    ///
+    /// Defaults to `false`.
+    pub fn enable_false_start(&mut self, enable: bool) -> &mut TlsConnectorBuilder {
+        self.false_start = enable;
+        self
+    }
+
+    /// Requires the server to negotiate the Extended Master Secret extension defined in
+    /// [RFC 7627].
+    ///
+    /// The extension binds the master secret to the handshake, protecting TLS 1.2 and earlier
+    /// against the triple handshake attack. Connections to servers that don't support it fail
+    /// once the handshake completes. TLS 1.3 always binds its secrets to the handshake, so it
+    /// satisfies the requirement. Only the OpenSSL backend supports it: building the connector
+    /// fails before OpenSSL 1.1.1, and other backends return an error from `connect` if it's
+    /// enabled.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// [RFC 7627]: https://tools.ietf.org/html/rfc7627
+    pub fn require_extended_master_secret(&mut self, require: bool) -> &mut TlsConnectorBuilder {
+        self.require_extended_master_secret = require;
+        self
+    }
+
+    /// Sets a callback which is passed each step of the handshake as it happens.
+    ///
+    /// This is intended for instrumentation, such as timing the phases of the handshake, and
+    /// can't affect its outcome. Nothing is reported once the handshake has completed. Only the
+    /// OpenSSL backend supports it: building the connector fails before OpenSSL 1.1.1, and other
+    /// backends return an error from `connect` if a callback is set.
+    ///
+    /// Defaults to no callback.
+    pub fn on_handshake_event(
+        &mut self,
+        f: impl Fn(HandshakeEvent) + Send + Sync + 'static,
+    ) -> &mut TlsConnectorBuilder {
+        self.handshake_event_callback = Some(Arc::new(f));
+        self
+    }
+
+    /// Rereads the system's trusted root certificates when building connectors.
+    ///
+    /// On Android, the roots are read from disk by the first connector built and shared with every
+    /// connector built after it. This makes connectors built by this builder read them again, which
+    /// is only necessary if they have changed since. Other platforms consult the system's roots
+    /// directly, so this has no effect on them.
+    pub fn refresh_system_roots(&mut self) -> &mut TlsConnectorBuilder {
+        self.refresh_system_roots = true;
+        self
+    }
+
+    /// Authenticates with a pre-shared key rather than certificates.
+    ///
+    /// When set, PSK cipher suites are offered unless others are selected with `ciphers`, and any
+    /// identity set with `identity` is not used. This is only supported by the OpenSSL backend;
+    /// other backends return an error from `connect`.
+    ///
+    /// Defaults to no pre-shared key.
+    #[cfg(feature = "psk")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "psk")))]
+    pub fn psk(&mut self, identity: &[u8], key: &[u8]) -> &mut TlsConnectorBuilder {
+        self.psk = Some((identity.to_vec(), key.to_vec()));
+        self
+    }
+
+    /// Request specific protocols through ALPN (Application-Layer Protocol Negotiation).
+    ///
+    /// Defaults to no protocols.
+    #[cfg(feature = "alpn")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "alpn")))]
+    pub fn request_alpns(&mut self, protocols: &[&str]) -> &mut TlsConnectorBuilder {
+        self.alpn = protocols.iter().map(|s| (*s).to_owned()).collect();
+        self
+    }
+
+    /// Controls whether the server must select one of the protocols requested with
+    /// `request_alpns`.
+    ///
+    /// Servers which don't support ALPN ignore the request, so without this the handshake
+    /// succeeds with no negotiated protocol. When enabled, it fails instead. This has no effect if
+    /// no protocols are requested.
+    ///
+    /// Defaults to `false`.
+    #[cfg(feature = "alpn")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "alpn")))]
+    pub fn require_alpn(&mut self, require: bool) -> &mut TlsConnectorBuilder {
+        self.require_alpn = require;
+        self
+    }
+
+    /// Controls the use of certificate validation.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// # Warning
+    ///
+    /// You should think very carefully before using this method. If invalid certificates are trusted, *any*
+    /// certificate for *any* site will be trusted for use. This includes expired certificates. This introduces
+    /// significant vulnerabilities, and should only be used as a last resort.
+    pub fn danger_accept_invalid_certs(
+        &mut self,
+        accept_invalid_certs: bool,
+    ) -> &mut TlsConnectorBuilder {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    /// Controls the use of Server Name Indication (SNI).
+    ///
+    /// Disabling SNI only stops a name from being sent to the server; the server's certificate is
+    /// still verified against the domain passed to `connect`. The options combine as follows:
+    ///
+    /// | `use_sni` | `server_name_indication` | Name sent | Name verified |
+    /// |-----------|--------------------------|-----------|---------------|
+    /// | `true`    | `None`                   | domain    | domain        |
+    /// | `true`    | `Some(name)`             | `name`    | domain        |
+    /// | `false`   | any                      | none      | domain        |
+    ///
+    /// No name is verified if `danger_accept_invalid_hostnames` is set. Servers hosting several
+    /// sites may present a different certificate, or fail the handshake, if no name is sent.
+    ///
+    /// Defaults to `true`.
+    pub fn use_sni(&mut self, use_sni: bool) -> &mut TlsConnectorBuilder {
+        self.use_sni = use_sni;
+        self
+    }
+
+    /// Sets the name sent in the Server Name Indication (SNI) extension.
+    ///
+    /// The domain passed to `connect` is still used to verify the server's certificate. Setting
+    /// `None` sends the domain, and no name is sent if SNI is disabled with `use_sni`. Only the
+    /// OpenSSL backend supports this; other backends return an error from `connect` if a name
+    /// is set.
+    ///
+    /// Defaults to `None`.
+    pub fn server_name_indication(&mut self, sni: Option<String>) -> &mut TlsConnectorBuilder {
+        self.server_name_indication = sni;
+        self
+    }
+
+    /// Controls the use of hostname verification.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// # Warning
+    ///
+    /// You should think very carefully before using this method. If invalid hostnames are trusted, *any* valid
+    /// certificate for *any* site will be trusted for use. This introduces significant vulnerabilities, and should
+    /// only be used as a last resort.
+    pub fn danger_accept_invalid_hostnames(
+        &mut self,
+        accept_invalid_hostnames: bool,
+    ) -> &mut TlsConnectorBuilder {
+        self.accept_invalid_hostnames = accept_invalid_hostnames;
+        self
+    }
+
+    /// Sets a time limit for the handshake performed by `TlsConnector::connect`.
+    ///
+    /// Once the limit is reached, the next read from or write to the stream fails the handshake
+    /// with a `TimedOut` I/O error, so a server which sends its messages a byte at a time can't
+    /// extend it. If the stream returns a `WouldBlock` or `TimedOut` error before then, `connect`
+    /// retries the handshake. This is meant for blocking streams with a read timeout shorter than
+    /// this limit, such as a `TcpStream` configured with `set_read_timeout`; a read that blocks
+    /// indefinitely can't be interrupted. Nonblocking streams shouldn't use this, as their callers
+    /// are responsible for timing the handshake themselves, and it doesn't apply to asynchronous
+    /// handshakes.
+    ///
+    /// Defaults to `None`.
+    pub fn handshake_timeout(&mut self, timeout: Option<Duration>) -> &mut TlsConnectorBuilder {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Controls whether the stream is flushed after every write.
+    ///
+    /// When the underlying stream buffers its writes, this sends each record as soon as it's
+    /// written rather than when the `TlsStream` is next flushed, which helps latency-sensitive
+    /// protocols exchanging small messages.
+    ///
+    /// Defaults to `false`.
+    pub fn flush_after_write(&mut self, always: bool) -> &mut TlsConnectorBuilder {
+        self.flush_after_write = always;
+        self
+    }
+
+    /// Creates a new `TlsConnector`.
+    pub fn build(&self) -> Result<TlsConnector> {
+        let connector = imp::TlsConnector::new(self)?;
+        Ok(TlsConnector(
+            Arc::new(connector),
+            self.handshake_timeout,
+            self.flush_after_write,
+        ))
     }
 }
 
 /// A builder for client-side TLS connections.
 ///
+/// Cloning a connector is cheap, and clones share its configuration, including the root
+/// certificates.
+///
 /// # Examples
 ///
 /// ```rust
@@ -468,7 +1596,7 @@ impl TlsConnectorBuilder {
 /// println!("{}", String::from_utf8_lossy(&res));
 /// ```
 #[derive(Clone, Debug)]
-pub struct TlsConnector(imp::TlsConnector);
+pub struct TlsConnector(Arc<imp::TlsConnector>, Option<Duration>, bool);
 
 impl TlsConnector {
     /// Returns a new connector with default settings.
@@ -480,22 +1608,56 @@ impl TlsConnector {
     pub fn builder() -> TlsConnectorBuilder {
         TlsConnectorBuilder {
             identity: None,
+            client_identities: vec![],
             min_protocol: Some(Protocol::Tlsv10),
             max_protocol: None,
             root_certificates: vec![],
+            #[cfg(target_os = "windows")]
+            root_store: None,
+            #[cfg(target_os = "windows")]
+            system_stores: vec![],
             use_sni: true,
+            server_name_indication: None,
             accept_invalid_certs: false,
             accept_invalid_hostnames: false,
             disable_built_in_roots: false,
+            ciphers: vec![],
+            signature_algorithms: vec![],
+            elliptic_curves: vec![],
+            pinned_public_keys: vec![],
+            pinned_certificates: vec![],
+            key_log_callback: None,
+            verify_callback: None,
+            verification_failure_callback: None,
+            revocation_mode: None,
+            handshake_timeout: None,
+            flush_after_write: false,
+            allow_unknown_revocation: false,
+            ignored_validation_errors: vec![],
+            require_stapled_ocsp: false,
+            session_tickets: false,
+            session_cache_capacity: 64,
+            credential_caching: true,
+            max_chain_depth: None,
+            max_fragment_length: None,
+            false_start: false,
+            require_extended_master_secret: false,
+            handshake_event_callback: None,
+            refresh_system_roots: false,
+            #[cfg(feature = "psk")]
+            psk: None,
             #[cfg(feature = "alpn")]
             alpn: vec![],
+            #[cfg(feature = "alpn")]
+            require_alpn: false,
         }
     }
 
     /// Initiates a TLS handshake.
     ///
     /// The provided domain will be used for both SNI and certificate hostname
-    /// validation.
+    /// validation. An IP address is instead matched against the IP addresses
+    /// in the certificate's subject alternative names, and is not sent with SNI.
     ///
     /// If the socket is nonblocking and a `WouldBlock` error is returned during
     /// the handshake, a `HandshakeError::WouldBlock` error will be returned
@@ -512,8 +1674,75 @@ impl TlsConnector {
     where
         S: io::Read + io::Write,
     {
-        let s = self.0.connect(domain, stream)?;
-        Ok(TlsStream(s))
+        let timeout = self.1.map(|timeout| (Instant::now(), timeout));
+        let result = self.start_connect(domain, StreamSlot::new(stream, timeout));
+        match timeout {
+            Some((start, timeout)) => handshake_within(result, start, timeout),
+            None => result,
+        }
+    }
+
+    /// Initiates a TLS handshake, taking ownership of the domain.
+    ///
+    /// This behaves exactly like `connect`, and is convenient when the domain is only available
+    /// as a `String` that would otherwise need to outlive the call.
+    pub fn connect_owned<S>(
+        &self,
+        domain: String,
+        stream: S,
+    ) -> result::Result<TlsStream<S>, HandshakeError<S>>
+    where
+        S: io::Read + io::Write,
+    {
+        self.connect(&domain, stream)
+    }
+
+    /// Performs a TLS handshake, retrying until it either completes or fails.
+    ///
+    /// Handshakes can be interrupted even over blocking streams, for example while SChannel
+    /// validates the server's certificate. This retries the handshake whenever it's interrupted, so
+    /// `HandshakeError::WouldBlock` is never returned. It must not be used with nonblocking streams,
+    /// since it would spin until the stream is ready. The domain is used as in `connect`.
+    pub fn connect_blocking<S>(
+        &self,
+        domain: &str,
+        stream: S,
+    ) -> result::Result<TlsStream<S>, HandshakeError<S>>
+    where
+        S: io::Read + io::Write,
+    {
+        let mut result = self.connect(domain, stream);
+        while let Err(HandshakeError::WouldBlock(s)) = result {
+            result = s.handshake();
+        }
+        result
+    }
+
+    fn start_connect<S>(
+        &self,
+        domain: &str,
+        stream: StreamSlot<S>,
+    ) -> result::Result<TlsStream<S>, HandshakeError<S>>
+    where
+        S: io::Read + io::Write,
+    {
+        match self.0.connect(domain, stream) {
+            Ok(s) => Ok(TlsStream(s, StreamState::new(self.2))),
+            Err(e) => Err(HandshakeError::from(e).flush_after_write(self.2)),
+        }
+    }
+
+    /// Initiates a TLS handshake over an asynchronous stream.
+    ///
+    /// The handshake is driven by polling the returned future, which resolves to a stream
+    /// implementing tokio's `AsyncRead` and `AsyncWrite`. The domain is used as in `connect`.
+    #[cfg(feature = "tokio")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+    pub fn connect_async<S>(&self, domain: &str, stream: S) -> ConnectAsync<S>
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        ConnectAsync::new(self.clone(), domain, stream)
     }
 }
 
@@ -522,6 +1751,38 @@ pub struct TlsAcceptorBuilder {
     identity: Identity,
     min_protocol: Option<Protocol>,
     max_protocol: Option<Protocol>,
+    ciphers: Vec<CipherSuite>,
+    prefer_server_cipher_order: bool,
+    #[cfg_attr(
+        any(target_os = "macos", target_os = "ios", target_os = "windows"),
+        allow(dead_code)
+    )]
+    allow_renegotiation: bool,
+    client_cert_mode: ClientCertMode,
+    #[cfg_attr(
+        any(target_os = "macos", target_os = "ios", target_os = "windows"),
+        allow(dead_code)
+    )]
+    client_cas: Vec<Certificate>,
+    #[cfg_attr(
+        any(target_os = "macos", target_os = "ios", target_os = "windows"),
+        allow(dead_code)
+    )]
+    client_cert_verify_callback: Option<ClientCertVerifyCallback>,
+    #[cfg_attr(
+        any(target_os = "macos", target_os = "ios", target_os = "windows"),
+        allow(dead_code)
+    )]
+    server_name_identities: Vec<(String, Identity)>,
+    ocsp_response: Option<Vec<u8>>,
+    handshake_timeout: Option<Duration>,
+    #[cfg_attr(
+        any(target_os = "macos", target_os = "ios", target_os = "windows"),
+        allow(dead_code)
+    )]
+    credential_caching: bool,
+    #[cfg(feature = "alpn")]
+    alpn: Vec<String>,
 }
 
 impl TlsAcceptorBuilder {
@@ -545,15 +1806,216 @@ impl TlsAcceptorBuilder {
         self
     }
 
+    /// Restricts the cipher suites which may be negotiated.
+    ///
+    /// See `TlsConnectorBuilder::ciphers` for details.
+    ///
+    /// Defaults to an empty list, which uses the platform's default suites.
+    pub fn ciphers(&mut self, suites: &[CipherSuite]) -> &mut TlsAcceptorBuilder {
+        self.ciphers = suites.to_vec();
+        self
+    }
+
+    /// Controls whether the acceptor's cipher suite preferences take priority over the client's.
+    ///
+    /// When enabled, the first suite in the acceptor's list which the client also supports is
+    /// negotiated. The acceptor's list is the one set with `ciphers`, or the platform's default
+    /// suites if none are set. When disabled, the client's order is used instead.
+    ///
+    /// Secure Transport and SChannel don't allow this to be configured, and will fail to accept
+    /// connections if it is disabled.
+    ///
+    /// Defaults to `true`.
+    pub fn prefer_server_cipher_order(&mut self, prefer: bool) -> &mut TlsAcceptorBuilder {
+        self.prefer_server_cipher_order = prefer;
+        self
+    }
+
+    /// Controls whether clients may renegotiate the session.
+    ///
+    /// Renegotiation only exists in TLS 1.2 and earlier. It is unrelated to TLS 1.3's
+    /// post-handshake authentication, which this setting doesn't affect.
+    ///
+    /// Only the OpenSSL backend supports this, with OpenSSL 1.1.1 or newer. Secure Transport and
+    /// SChannel don't allow it to be configured, and keep their platform defaults.
+    ///
+    /// Defaults to `false`.
+    pub fn allow_renegotiation(&mut self, allow: bool) -> &mut TlsAcceptorBuilder {
+        self.allow_renegotiation = allow;
+        self
+    }
+
+    /// Controls whether a certificate is requested from the client.
+    ///
+    /// Client certificates are verified against the certificates added with `add_client_ca`.
+    /// The verified certificate is available from `TlsStream::peer_certificate`.
+    ///
+    /// Only the OpenSSL backend supports this. Secure Transport and SChannel will fail to accept
+    /// connections if it's set to anything but `ClientCertMode::None`.
+    ///
+    /// Defaults to `ClientCertMode::None`.
+    pub fn client_cert_verification(&mut self, mode: ClientCertMode) -> &mut TlsAcceptorBuilder {
+        self.client_cert_mode = mode;
+        self
+    }
+
+    /// Adds a certificate authority which client certificates are verified against.
+    ///
+    /// Defaults to an empty set.
+    pub fn add_client_ca(&mut self, cert: Certificate) -> &mut TlsAcceptorBuilder {
+        self.client_cas.push(cert);
+        self
+    }
+
+    /// Sets a callback which authorizes the client's certificate chain.
+    ///
+    /// The callback is passed the chain, starting with the client's certificate, once it has been
+    /// verified against the client CAs. Returning an error aborts the handshake. It isn't called
+    /// if certificates aren't requested with `client_cert_verification`, or if the client doesn't
+    /// present one.
+    ///
+    /// Defaults to no callback.
+    pub fn client_cert_verify_callback(
+        &mut self,
+        f: impl Fn(&[Certificate]) -> Result<()> + Send + Sync + 'static,
+    ) -> &mut TlsAcceptorBuilder {
+        self.client_cert_verify_callback = Some(Arc::new(f));
+        self
+    }
+
+    /// Adds an identity to present to clients which request `sni` with Server Name Indication.
+    ///
+    /// Names are compared case-insensitively. Clients which request any other name, or none at
+    /// all, are presented with the identity the builder was created with.
+    ///
+    /// Only the OpenSSL backend supports this. Secure Transport and SChannel will fail to accept
+    /// connections if any identities have been added.
+    pub fn add_server_name_identity(
+        &mut self,
+        sni: &str,
+        identity: Identity,
+    ) -> &mut TlsAcceptorBuilder {
+        self.server_name_identities.push((sni.to_owned(), identity));
+        self
+    }
+
+    /// Sets a DER-encoded OCSP response to staple to the acceptor's certificate.
+    ///
+    /// The response is sent to clients which request it. It is not fetched or refreshed
+    /// automatically; the caller must obtain it from the certificate's OCSP responder, and build a
+    /// new `TlsAcceptor` to replace it before it expires.
+    ///
+    /// Only the OpenSSL backend supports this. Secure Transport and SChannel will fail to accept
+    /// connections if a response is set.
+    ///
+    /// Defaults to no response.
+    pub fn ocsp_response(&mut self, der: Vec<u8>) -> &mut TlsAcceptorBuilder {
+        self.ocsp_response = Some(der);
+        self
+    }
+
+    /// Sets a time limit for the handshake performed by `TlsAcceptor::accept`.
+    ///
+    /// This bounds how long a client which stalls midway through its handshake, or trickles it a
+    /// byte at a time, can hold the connection. As with `TlsConnectorBuilder::handshake_timeout`,
+    /// the handshake fails with a `TimedOut` I/O error on the first read or write after the limit,
+    /// and `accept` retries it whenever the stream returns a `WouldBlock` or `TimedOut` error
+    /// before then. The stream should have a read timeout shorter than this limit, such as a
+    /// `TcpStream` configured with `set_read_timeout`, since a read that blocks indefinitely can't
+    /// be interrupted.
+    ///
+    /// Defaults to `None`.
+    pub fn handshake_timeout(&mut self, timeout: Option<Duration>) -> &mut TlsAcceptorBuilder {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Controls whether sessions are cached so that returning clients can resume them.
+    ///
+    /// When disabled, the acceptor neither caches sessions nor issues session tickets, so every
+    /// client performs a full handshake. Only the OpenSSL backend resumes sessions on the server
+    /// side; the other backends always perform a full handshake.
+    ///
+    /// Defaults to `true`.
+    pub fn credential_caching(&mut self, enabled: bool) -> &mut TlsAcceptorBuilder {
+        self.credential_caching = enabled;
+        self
+    }
+
+    /// Sets the protocols the acceptor supports through ALPN (Application-Layer Protocol
+    /// Negotiation), in order of preference.
+    ///
+    /// The first of these protocols which the client also offers is selected. If the client
+    /// offers none of them, the handshake fails with a `no_application_protocol` alert. Clients
+    /// which don't use ALPN are unaffected.
+    ///
+    /// Only the OpenSSL backend supports this. Secure Transport and SChannel will fail to accept
+    /// connections if any protocols are set.
+    ///
+    /// Defaults to no protocols.
+    #[cfg(feature = "alpn")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "alpn")))]
+    pub fn alpn_protocols(&mut self, protocols: &[&str]) -> &mut TlsAcceptorBuilder {
+        self.alpn = protocols.iter().map(|s| (*s).to_owned()).collect();
+        self
+    }
+
     /// Creates a new `TlsAcceptor`.
     pub fn build(&self) -> Result<TlsAcceptor> {
         let acceptor = imp::TlsAcceptor::new(self)?;
-        Ok(TlsAcceptor(acceptor))
+        Ok(TlsAcceptor(acceptor, self.handshake_timeout))
+    }
+}
+
+// Retries an interrupted handshake until it completes, fails, or runs past the timeout. The
+// stream enforces the timeout on every read and write, so a peer which keeps sending a byte at a
+// time can't extend the handshake either.
+fn handshake_within<S>(
+    mut result: result::Result<TlsStream<S>, HandshakeError<S>>,
+    start: Instant,
+    timeout: Duration,
+) -> result::Result<TlsStream<S>, HandshakeError<S>>
+where
+    S: io::Read + io::Write,
+{
+    loop {
+        result = match result {
+            Ok(mut s) => {
+                s.0.get_mut().timeout = None;
+                return Ok(s);
+            }
+            Err(HandshakeError::WouldBlock(mut s)) => {
+                if start.elapsed() >= timeout {
+                    return Err(HandshakeError::Failure {
+                        error: Error::from(handshake_timed_out()),
+                        stream: s.0.get_mut().stream.take(),
+                    });
+                }
+                s.handshake()
+            }
+            // The backends wrap the stream's error in their own, so report the timeout directly.
+            Err(HandshakeError::Failure { stream, .. }) if start.elapsed() >= timeout => {
+                return Err(HandshakeError::Failure {
+                    error: Error::from(handshake_timed_out()),
+                    stream,
+                });
+            }
+            Err(e) => return Err(e),
+        };
     }
 }
 
+fn handshake_timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "the TLS handshake timed out")
+}
+
 /// A builder for server-side TLS connections.
 ///
+/// An acceptor presents the identity it was built with for its whole lifetime. To rotate
+/// certificates, build a new `TlsAcceptor` and use it for subsequent connections; its
+/// credentials and session cache are not shared with the old one, so clients perform a full
+/// handshake against the new identity.
+///
 /// # Examples
 ///
 /// ```rust,no_run
@@ -591,7 +2053,7 @@ impl TlsAcceptorBuilder {
 /// }
 /// ```
 #[derive(Clone)]
-pub struct TlsAcceptor(imp::TlsAcceptor);
+pub struct TlsAcceptor(imp::TlsAcceptor, Option<Duration>);
 
 impl TlsAcceptor {
     /// Creates a acceptor with default settings.
@@ -609,6 +2071,18 @@ impl TlsAcceptor {
             identity,
             min_protocol: Some(Protocol::Tlsv10),
             max_protocol: None,
+            ciphers: vec![],
+            prefer_server_cipher_order: true,
+            allow_renegotiation: false,
+            client_cert_mode: ClientCertMode::None,
+            client_cas: vec![],
+            client_cert_verify_callback: None,
+            server_name_identities: vec![],
+            ocsp_response: None,
+            handshake_timeout: None,
+            credential_caching: true,
+            #[cfg(feature = "alpn")]
+            alpn: vec![],
         }
     }
 
@@ -622,15 +2096,54 @@ impl TlsAcceptor {
     where
         S: io::Read + io::Write,
     {
-        match self.0.accept(stream) {
-            Ok(s) => Ok(TlsStream(s)),
+        let timeout = self.1.map(|timeout| (Instant::now(), timeout));
+        let result = match self.0.accept(StreamSlot::new(stream, timeout)) {
+            Ok(s) => Ok(TlsStream(s, StreamState::new(false))),
             Err(e) => Err(e.into()),
+        };
+        match timeout {
+            Some((start, timeout)) => handshake_within(result, start, timeout),
+            None => result,
         }
     }
+
+    /// Returns a copy of this acceptor which accepts a different range of protocol versions.
+    ///
+    /// The bounds replace those set by `TlsAcceptorBuilder::min_protocol_version` and
+    /// `TlsAcceptorBuilder::max_protocol_version`, and `None` has the same meaning. All other
+    /// settings, including the identity, are shared with this acceptor, so this is much cheaper
+    /// than building a new one.
+    ///
+    /// With the OpenSSL backend, this requires OpenSSL 1.1.0 or newer; with older versions `accept`
+    /// returns an error.
+    pub fn with_protocol_bounds(
+        &self,
+        min: Option<Protocol>,
+        max: Option<Protocol>,
+    ) -> TlsAcceptor {
+        TlsAcceptor(self.0.with_protocol_bounds(min, max), self.1)
+    }
 }
 
 /// A stream managing a TLS session.
-pub struct TlsStream<S>(imp::TlsStream<S>);
+pub struct TlsStream<S>(imp::TlsStream<StreamSlot<S>>, StreamState);
+
+struct StreamState {
+    flush_after_write: bool,
+    // The plaintext transferred since the handshake completed.
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
+impl StreamState {
+    fn new(flush_after_write: bool) -> StreamState {
+        StreamState {
+            flush_after_write,
+            bytes_read: 0,
+            bytes_written: 0,
+        }
+    }
+}
 
 impl<S: fmt::Debug> fmt::Debug for TlsStream<S> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
@@ -641,27 +2154,142 @@ impl<S: fmt::Debug> fmt::Debug for TlsStream<S> {
 impl<S> TlsStream<S> {
     /// Returns a shared reference to the inner stream.
     pub fn get_ref(&self) -> &S {
-        self.0.get_ref()
+        self.0.get_ref().get_ref()
     }
 
     /// Returns a mutable reference to the inner stream.
     pub fn get_mut(&mut self) -> &mut S {
-        self.0.get_mut()
+        self.0.get_mut().get_mut()
+    }
+
+    /// Consumes the `TlsStream`, returning the inner stream.
+    ///
+    /// No shutdown is performed, and any data already decrypted but not yet read is lost. Use
+    /// `into_inner_with_buffer` to keep it, or drain the stream first.
+    pub fn into_inner(mut self) -> S {
+        self.0.get_mut().take()
+    }
+
+    /// Splits the stream into halves which can be read from and written to independently, for
+    /// example from different threads.
+    ///
+    /// The underlying stream must support reads and writes through shared references, as
+    /// `TcpStream` does. The read half reads from it without holding any lock, so waiting for the
+    /// peer doesn't hold up the write half. The TLS session itself is shared behind a mutex, which
+    /// each write and flush holds until it returns, as does the read half while it decrypts what
+    /// it has read. A write which blocks on the underlying stream therefore delays reads until it
+    /// completes. The halves can be rejoined with `ReadHalf::unsplit`.
+    pub fn split(self) -> (ReadHalf<S>, WriteHalf<S>)
+    where
+        for<'a> &'a S: io::Read + io::Write,
+    {
+        ReadHalf::new(self)
+    }
+
+    /// Returns the number of bytes of plaintext read from the stream since the handshake
+    /// completed.
+    pub fn bytes_read(&self) -> u64 {
+        self.1.bytes_read
+    }
+
+    /// Returns the number of bytes of plaintext written to the stream since the handshake
+    /// completed.
+    pub fn bytes_written(&self) -> u64 {
+        self.1.bytes_written
+    }
+
+    /// Returns `true` if the stream was created by a `TlsAcceptor`.
+    pub fn is_server(&self) -> bool {
+        self.0.is_server()
+    }
+
+    /// Returns `true` if the stream was created by a `TlsConnector`.
+    pub fn is_client(&self) -> bool {
+        !self.0.is_server()
     }
 }
 
 impl<S: io::Read + io::Write> TlsStream<S> {
+    /// Consumes the `TlsStream`, returning the inner stream along with any data that was
+    /// already decrypted but not yet read.
+    ///
+    /// No shutdown is performed.
+    pub fn into_inner_with_buffer(mut self) -> (S, Vec<u8>) {
+        let mut buf = vec![0; self.buffered_read_size().unwrap_or(0)];
+        let mut len = 0;
+        while len < buf.len() {
+            match io::Read::read(&mut self, &mut buf[len..]) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => len += n,
+            }
+        }
+        buf.truncate(len);
+        (self.into_inner(), buf)
+    }
+
     /// Returns the number of bytes that can be read without resulting in any
     /// network calls.
     pub fn buffered_read_size(&self) -> Result<usize> {
         Ok(self.0.buffered_read_size()?)
     }
 
+    /// Reads decrypted data into `buf` without consuming it.
+    ///
+    /// Data is read from the transport if none is buffered. The same data will be returned by the
+    /// next call to `read`.
+    pub fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.peek(buf)
+    }
+
+    /// Reads decrypted data into a buffer which may be uninitialized.
+    ///
+    /// This behaves like `read`, but doesn't require `buf` to be zeroed first. If `Ok(n)` is
+    /// returned, the first `n` bytes of `buf` have been initialized.
+    pub fn read_uninit(&mut self, buf: &mut [MaybeUninit<u8>]) -> io::Result<usize> {
+        let len = self.0.read_uninit(buf)?;
+        self.1.bytes_read += len as u64;
+        Ok(len)
+    }
+
     /// Returns the peer's leaf certificate, if available.
     pub fn peer_certificate(&self) -> Result<Option<Certificate>> {
         Ok(self.0.peer_certificate()?.map(Certificate))
     }
 
+    /// Returns the peer's certificate chain, leaf first.
+    ///
+    /// Returns an empty chain if the peer did not present a certificate. Depending on the
+    /// implementation, the chain may be the one presented by the peer or the one built while
+    /// verifying it.
+    pub fn peer_certificate_chain(&self) -> Result<Vec<Certificate>> {
+        Ok(self
+            .0
+            .peer_certificate_chain()?
+            .into_iter()
+            .map(Certificate)
+            .collect())
+    }
+
+    /// Returns the error found while validating the peer's certificate chain, if it was accepted
+    /// anyway.
+    ///
+    /// This is only recorded when the chain is accepted because of
+    /// `TlsConnectorBuilder::danger_accept_invalid_certs`, so it can be used to report why the
+    /// connection is insecure.
+    pub fn certificate_validation_result(&self) -> Option<Error> {
+        self.0.certificate_validation_result().map(Error::from)
+    }
+
+    /// Returns the trusted root certificate which anchored the peer's validated certificate chain.
+    ///
+    /// This can be used to tell whether one of the certificates added with
+    /// `TlsConnectorBuilder::add_root_certificate` or a built-in root was used. Returns `Ok(None)`
+    /// if the chain wasn't successfully validated, such as when it was accepted because of
+    /// `TlsConnectorBuilder::danger_accept_invalid_certs`.
+    pub fn trust_anchor(&self) -> Result<Option<Certificate>> {
+        Ok(self.0.trust_anchor()?.map(Certificate))
+    }
+
     /// Returns the tls-server-end-point channel binding data as defined in [RFC 5929].
     ///
     /// [RFC 5929]: https://tools.ietf.org/html/rfc5929
@@ -669,14 +2297,162 @@ impl<S: io::Read + io::Write> TlsStream<S> {
         Ok(self.0.tls_server_end_point()?)
     }
 
+    /// Returns the tls-unique channel binding data as defined in [RFC 5929].
+    ///
+    /// This is the first Finished message of the handshake. It is not defined for TLS 1.3, so
+    /// `Ok(None)` is returned for those sessions. Returns an error if the implementation is
+    /// unable to report it.
+    ///
+    /// [RFC 5929]: https://tools.ietf.org/html/rfc5929
+    pub fn tls_unique(&self) -> Result<Option<Vec<u8>>> {
+        Ok(self.0.tls_unique()?)
+    }
+
+    /// Returns the protocol version negotiated by the handshake.
+    ///
+    /// Returns `None` if the version isn't one of the variants of `Protocol`. Returns an error if
+    /// the implementation is unable to report it, which includes the SChannel backend.
+    pub fn protocol_version(&self) -> Result<Option<Protocol>> {
+        Ok(self.0.protocol_version()?)
+    }
+
+    /// Returns the cipher suite negotiated by the handshake.
+    ///
+    /// Returns an error if the implementation is unable to report it, which includes the SChannel
+    /// backend and the OpenSSL backend before OpenSSL 1.1.1.
+    pub fn negotiated_cipher_suite(&self) -> Result<Option<CipherSuite>> {
+        Ok(self.0.negotiated_cipher_suite()?)
+    }
+
+    /// Returns the group used for the handshake's key exchange.
+    ///
+    /// Returns `None` if the key exchange didn't use one of the `NamedGroup`s, such as with RSA
+    /// key transport. Returns an error if the implementation is unable to report it, which
+    /// includes the OpenSSL backend before OpenSSL 3.0.
+    pub fn key_exchange_group(&self) -> Result<Option<NamedGroup>> {
+        Ok(self.0.key_exchange_group()?)
+    }
+
+    /// Returns whether the negotiated cipher suite provides forward secrecy.
+    ///
+    /// Suites with an ephemeral ECDHE or DHE key exchange, and all TLS 1.3 suites, provide forward
+    /// secrecy, while suites using RSA key transport don't. Returns an error if the implementation
+    /// is unable to report the cipher suite, or if its key exchange isn't recognized.
+    pub fn has_forward_secrecy(&self) -> Result<bool> {
+        let suite = self.negotiated_cipher_suite()?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                "unable to determine the negotiated cipher suite",
+            )
+        })?;
+        let forward_secret = cipher_suite::forward_secret(suite.id(), suite.name());
+        Ok(forward_secret.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                format!("unknown key exchange for {}", suite.name()),
+            )
+        })?)
+    }
+
+    /// Returns whether the handshake resumed a previous session rather than performing a full
+    /// handshake.
+    ///
+    /// Returns an error if the implementation is unable to report it.
+    pub fn session_resumed(&self) -> Result<bool> {
+        Ok(self.0.session_resumed()?)
+    }
+
+    /// Returns the session ID, for correlating the two ends of a connection in logs.
+    ///
+    /// This is best-effort: TLS 1.3 only keeps the session ID for compatibility, so `Ok(None)` is
+    /// returned for those sessions, as it is when the server didn't assign an ID. A server which
+    /// issues a session ticket may not assign one, and OpenSSL clients then derive an ID from the
+    /// ticket which the server doesn't know. Only the OpenSSL backend supports it; other backends
+    /// return an error.
+    pub fn session_id(&self) -> Result<Option<Vec<u8>>> {
+        Ok(self.0.session_id()?)
+    }
+
+    /// Returns whether the session's master secret is bound to its handshake, either by the
+    /// Extended Master Secret extension or by TLS 1.3.
+    ///
+    /// Returns an error if the implementation is unable to report it.
+    pub fn extended_master_secret_used(&self) -> Result<bool> {
+        Ok(self.0.extended_master_secret_used()?)
+    }
+
+    /// Returns the DER-encoded OCSP response stapled by the server, if it provided one.
+    ///
+    /// Returns an error if the implementation is unable to report it.
+    pub fn ocsp_response(&self) -> Result<Option<Vec<u8>>> {
+        Ok(self.0.ocsp_response()?)
+    }
+
+    /// Returns whether the peer has closed the session with a `close_notify` alert.
+    ///
+    /// A `read` returning 0 when this is false means the connection was closed without a TLS
+    /// shutdown, and the data may have been truncated.
+    ///
+    /// Returns an error if the implementation is unable to report it.
+    pub fn received_close_notify(&self) -> Result<bool> {
+        Ok(self.0.received_close_notify()?)
+    }
+
+    /// Derives keying material from the session as defined in [RFC 5705].
+    ///
+    /// Returns an error if the implementation does not support keying material exporters.
+    ///
+    /// [RFC 5705]: https://tools.ietf.org/html/rfc5705
+    pub fn export_keying_material(
+        &self,
+        label: &[u8],
+        context: Option<&[u8]>,
+        out_len: usize,
+    ) -> Result<Vec<u8>> {
+        Ok(self.0.export_keying_material(label, context, out_len)?)
+    }
+
     /// Returns the negotiated ALPN protocol.
-    #[cfg(feature = "alpn")]
-    #[cfg_attr(docsrs, doc(cfg(feature = "alpn")))]
+    ///
+    /// Protocols can only be requested with the `alpn` feature, so this returns `Ok(None)` if it
+    /// is disabled.
     pub fn negotiated_alpn(&self) -> Result<Option<Vec<u8>>> {
         Ok(self.0.negotiated_alpn()?)
     }
 
+    /// Returns the negotiated ALPN protocol as a string borrowed from the stream.
+    ///
+    /// Protocol IDs such as `h2` and `http/1.1` are ASCII, so an error is returned if the peer
+    /// negotiated one that isn't valid UTF-8. `negotiated_alpn` returns the bytes of any protocol.
+    pub fn negotiated_alpn_str(&self) -> Result<Option<&str>> {
+        match self.0.negotiated_alpn_ref()? {
+            Some(alpn) => match str::from_utf8(alpn) {
+                Ok(alpn) => Ok(Some(alpn)),
+                Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e).into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the keys protecting the session.
+    ///
+    /// With TLS 1.3 this sends a KeyUpdate message, which also asks the peer to update its own
+    /// keys. With earlier versions the session is renegotiated, which involves a full handshake and
+    /// fails if the peer doesn't allow renegotiation, as is the default for a `TlsAcceptor` (see
+    /// `TlsAcceptorBuilder::allow_renegotiation`).
+    ///
+    /// Only the OpenSSL backend supports this; other backends return an error.
+    pub fn refresh_keys(&mut self) -> io::Result<()> {
+        self.0.refresh_keys()
+    }
+
     /// Shuts down the TLS session.
+    ///
+    /// This sends a `close_notify` alert to the peer, but doesn't wait for the peer's own alert.
+    ///
+    /// If the underlying stream is nonblocking, this may fail with `io::ErrorKind::WouldBlock`
+    /// before the alert has been written. The shutdown is resumed by calling this method again
+    /// once the stream is writable, until it returns `Ok(())`.
     pub fn shutdown(&mut self) -> io::Result<()> {
         self.0.shutdown()?;
         Ok(())
@@ -685,13 +2461,52 @@ impl<S: io::Read + io::Write> TlsStream<S> {
 
 impl<S: io::Read + io::Write> io::Read for TlsStream<S> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
+        let len = self.0.read(buf)?;
+        self.1.bytes_read += len as u64;
+        Ok(len)
     }
 }
 
+// The largest amount of data that fits in a single TLS record.
+const MAX_PLAINTEXT_LEN: usize = 16384;
+
 impl<S: io::Read + io::Write> io::Write for TlsStream<S> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf)
+        let len = self.0.write(buf)?;
+        self.1.bytes_written += len as u64;
+        if self.1.flush_after_write {
+            match self.0.flush() {
+                // The data has been accepted, and will be sent by a later flush.
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                r => r?,
+            }
+        }
+        Ok(len)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        // Coalesce the slices into a single record's worth of data so they're encrypted together
+        // rather than as a record each.
+        let first = match bufs.iter().find(|b| !b.is_empty()) {
+            Some(first) => first,
+            None => return Ok(0),
+        };
+        let total = bufs
+            .iter()
+            .fold(0usize, |total, b| total.saturating_add(b.len()));
+        if first.len() == total || first.len() >= MAX_PLAINTEXT_LEN {
+            return io::Write::write(self, first);
+        }
+
+        let mut buf = Vec::with_capacity(cmp::min(total, MAX_PLAINTEXT_LEN));
+        for b in bufs {
+            let len = cmp::min(b.len(), MAX_PLAINTEXT_LEN - buf.len());
+            buf.extend_from_slice(&b[..len]);
+            if buf.len() == MAX_PLAINTEXT_LEN {
+                break;
+            }
+        }
+        io::Write::write(self, &buf)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -699,6 +2514,133 @@ impl<S: io::Read + io::Write> io::Write for TlsStream<S> {
     }
 }
 
+// Wraps the stream handed to the backend so that `TlsStream::into_inner` can move it back out,
+// which not every backend supports. It also records the direction of the last operation that
+// would have blocked, for `MidHandshakeTlsStream::wants_read` and `wants_write`.
+struct StreamSlot<S> {
+    stream: Option<S>,
+    // The stream while the `TlsStream` is split. The read half reads from it without holding the
+    // session's lock, and passes what it reads to the backend through `received`.
+    shared: Option<split::SharedStream<S>>,
+    received: Vec<u8>,
+    received_eof: bool,
+    write_blocked: bool,
+    // The start and limit of a handshake with a timeout, until the handshake completes.
+    timeout: Option<(Instant, Duration)>,
+}
+
+impl<S> StreamSlot<S> {
+    fn new(stream: S, timeout: Option<(Instant, Duration)>) -> StreamSlot<S> {
+        StreamSlot {
+            stream: Some(stream),
+            shared: None,
+            received: vec![],
+            received_eof: false,
+            write_blocked: false,
+            timeout,
+        }
+    }
+
+    fn write_blocked(&self) -> bool {
+        self.write_blocked
+    }
+
+    fn get_ref(&self) -> &S {
+        match self.shared {
+            Some(ref shared) => shared.get_ref(),
+            None => self.stream.as_ref().expect("stream has been taken"),
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut S {
+        self.stream.as_mut().expect("stream has been taken")
+    }
+
+    fn take(&mut self) -> S {
+        self.stream.take().expect("stream has been taken")
+    }
+
+    fn check_timeout(&self) -> io::Result<()> {
+        match self.timeout {
+            Some((start, timeout)) if start.elapsed() >= timeout => Err(handshake_timed_out()),
+            _ => Ok(()),
+        }
+    }
+
+    fn record_block<T>(&mut self, r: &mut io::Result<T>, write: bool) {
+        if let Err(ref mut e) = *r {
+            // A read timeout is reported as `TimedOut` on Windows. Until the handshake's own limit
+            // is reached, treat it like `WouldBlock` so that the handshake is retried.
+            if e.kind() == io::ErrorKind::TimedOut && self.timeout.is_some() {
+                *e = io::ErrorKind::WouldBlock.into();
+            }
+            if e.kind() == io::ErrorKind::WouldBlock {
+                self.write_blocked = write;
+            }
+        }
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for StreamSlot<S> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.stream, &self.shared) {
+            (_, Some(shared)) => fmt::Debug::fmt(shared.get_ref(), fmt),
+            (Some(s), None) => fmt::Debug::fmt(s, fmt),
+            (None, None) => fmt.write_str("<taken>"),
+        }
+    }
+}
+
+impl<S: io::Read> io::Read for StreamSlot<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check_timeout()?;
+        let mut r = if !self.received.is_empty() {
+            let len = cmp::min(buf.len(), self.received.len());
+            buf[..len].copy_from_slice(&self.received[..len]);
+            self.received.drain(..len);
+            Ok(len)
+        } else if self.shared.is_some() {
+            // The read half reads more once the backend reports that it would block.
+            if self.received_eof {
+                Ok(0)
+            } else {
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+        } else {
+            match self.stream {
+                Some(ref mut s) => s.read(buf),
+                None => Err(io::ErrorKind::NotConnected.into()),
+            }
+        };
+        self.record_block(&mut r, false);
+        r
+    }
+}
+
+impl<S: io::Write> io::Write for StreamSlot<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_timeout()?;
+        let mut r = match (&mut self.stream, &self.shared) {
+            (_, Some(shared)) => shared.write(buf),
+            (Some(s), None) => s.write(buf),
+            (None, None) => Err(io::ErrorKind::NotConnected.into()),
+        };
+        self.record_block(&mut r, true);
+        r
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.check_timeout()?;
+        let mut r = match (&mut self.stream, &self.shared) {
+            (_, Some(shared)) => shared.flush(),
+            (Some(s), None) => s.flush(),
+            (None, None) => Err(io::ErrorKind::NotConnected.into()),
+        };
+        self.record_block(&mut r, true);
+        r
+    }
+}
+
 fn _check_kinds() {
     use std::net::TcpStream;
 
@@ -0,0 +1,198 @@
+//! Splitting of PEM data into its blocks.
+
+use std::io;
+use std::str;
+
+/// Split data by PEM guard lines
+pub struct PemBlock<'a> {
+    pem_block: &'a str,
+    cur_end: usize,
+}
+
+impl<'a> PemBlock<'a> {
+    pub fn new(data: &'a [u8]) -> io::Result<PemBlock<'a>> {
+        let s = str::from_utf8(data).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, "PEM data is not valid UTF-8")
+        })?;
+        Ok(PemBlock {
+            pem_block: s,
+            cur_end: s.find("-----BEGIN").unwrap_or(s.len()),
+        })
+    }
+}
+
+/// Encodes DER data as a PEM block with the given label, wrapping the base64 text at 64 columns.
+pub fn encode(label: &str, der: &[u8]) -> Vec<u8> {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut base64 = Vec::with_capacity(der.len() / 3 * 4 + 4);
+    for chunk in der.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                base64.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize]);
+            } else {
+                base64.push(b'=');
+            }
+        }
+    }
+
+    let mut pem = format!("-----BEGIN {}-----\n", label).into_bytes();
+    for line in base64.chunks(64) {
+        pem.extend_from_slice(line);
+        pem.push(b'\n');
+    }
+    pem.extend_from_slice(format!("-----END {}-----\n", label).as_bytes());
+    pem
+}
+
+impl<'a> Iterator for PemBlock<'a> {
+    type Item = &'a [u8];
+    fn next(&mut self) -> Option<Self::Item> {
+        let last = self.pem_block.len();
+        if self.cur_end >= last {
+            return None;
+        }
+        let begin = self.cur_end;
+        let pos = self.pem_block[begin + 1..].find("-----BEGIN");
+        self.cur_end = match pos {
+            Some(end) => end + begin + 1,
+            None => last,
+        };
+        Some(&self.pem_block.as_bytes()[begin..self.cur_end])
+    }
+}
+
+#[test]
+fn test_split() {
+    // Split three certs, CRLF line terminators.
+    assert_eq!(
+        PemBlock::new(
+            b"-----BEGIN FIRST-----\r\n-----END FIRST-----\r\n\
+        -----BEGIN SECOND-----\r\n-----END SECOND\r\n\
+        -----BEGIN THIRD-----\r\n-----END THIRD\r\n"
+        )
+        .unwrap()
+        .collect::<Vec<&[u8]>>(),
+        vec![
+            b"-----BEGIN FIRST-----\r\n-----END FIRST-----\r\n" as &[u8],
+            b"-----BEGIN SECOND-----\r\n-----END SECOND\r\n",
+            b"-----BEGIN THIRD-----\r\n-----END THIRD\r\n"
+        ]
+    );
+    // Split three certs, CRLF line terminators except at EOF.
+    assert_eq!(
+        PemBlock::new(
+            b"-----BEGIN FIRST-----\r\n-----END FIRST-----\r\n\
+        -----BEGIN SECOND-----\r\n-----END SECOND-----\r\n\
+        -----BEGIN THIRD-----\r\n-----END THIRD-----"
+        )
+        .unwrap()
+        .collect::<Vec<&[u8]>>(),
+        vec![
+            b"-----BEGIN FIRST-----\r\n-----END FIRST-----\r\n" as &[u8],
+            b"-----BEGIN SECOND-----\r\n-----END SECOND-----\r\n",
+            b"-----BEGIN THIRD-----\r\n-----END THIRD-----"
+        ]
+    );
+    // Split two certs, LF line terminators.
+    assert_eq!(
+        PemBlock::new(
+            b"-----BEGIN FIRST-----\n-----END FIRST-----\n\
+        -----BEGIN SECOND-----\n-----END SECOND\n"
+        )
+        .unwrap()
+        .collect::<Vec<&[u8]>>(),
+        vec![
+            b"-----BEGIN FIRST-----\n-----END FIRST-----\n" as &[u8],
+            b"-----BEGIN SECOND-----\n-----END SECOND\n"
+        ]
+    );
+    // Split two certs, CR line terminators.
+    assert_eq!(
+        PemBlock::new(
+            b"-----BEGIN FIRST-----\r-----END FIRST-----\r\
+        -----BEGIN SECOND-----\r-----END SECOND\r"
+        )
+        .unwrap()
+        .collect::<Vec<&[u8]>>(),
+        vec![
+            b"-----BEGIN FIRST-----\r-----END FIRST-----\r" as &[u8],
+            b"-----BEGIN SECOND-----\r-----END SECOND\r"
+        ]
+    );
+    // Split two certs, LF line terminators except at EOF.
+    assert_eq!(
+        PemBlock::new(
+            b"-----BEGIN FIRST-----\n-----END FIRST-----\n\
+        -----BEGIN SECOND-----\n-----END SECOND"
+        )
+        .unwrap()
+        .collect::<Vec<&[u8]>>(),
+        vec![
+            b"-----BEGIN FIRST-----\n-----END FIRST-----\n" as &[u8],
+            b"-----BEGIN SECOND-----\n-----END SECOND"
+        ]
+    );
+    // Split a single cert, LF line terminators.
+    assert_eq!(
+        PemBlock::new(b"-----BEGIN FIRST-----\n-----END FIRST-----\n")
+            .unwrap()
+            .collect::<Vec<&[u8]>>(),
+        vec![b"-----BEGIN FIRST-----\n-----END FIRST-----\n" as &[u8]]
+    );
+    // Split a single cert, LF line terminators except at EOF.
+    assert_eq!(
+        PemBlock::new(b"-----BEGIN FIRST-----\n-----END FIRST-----")
+            .unwrap()
+            .collect::<Vec<&[u8]>>(),
+        vec![b"-----BEGIN FIRST-----\n-----END FIRST-----" as &[u8]]
+    );
+    // (Don't) split garbage.
+    assert_eq!(
+        PemBlock::new(b"junk").unwrap().collect::<Vec<&[u8]>>(),
+        Vec::<&[u8]>::new()
+    );
+    assert_eq!(
+        PemBlock::new(b"junk-----BEGIN garbage")
+            .unwrap()
+            .collect::<Vec<&[u8]>>(),
+        vec![b"-----BEGIN garbage" as &[u8]]
+    );
+}
+
+#[test]
+fn test_invalid_utf8() {
+    let err = PemBlock::new(b"-----BEGIN FIRST-----\n\xff\n-----END FIRST-----\n")
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_encode() {
+    assert_eq!(
+        encode("TEST", b""),
+        b"-----BEGIN TEST-----\n-----END TEST-----\n".to_vec()
+    );
+    assert_eq!(
+        encode("TEST", b"f"),
+        b"-----BEGIN TEST-----\nZg==\n-----END TEST-----\n".to_vec()
+    );
+    assert_eq!(
+        encode("TEST", b"fo"),
+        b"-----BEGIN TEST-----\nZm8=\n-----END TEST-----\n".to_vec()
+    );
+    assert_eq!(
+        encode("TEST", b"foo"),
+        b"-----BEGIN TEST-----\nZm9v\n-----END TEST-----\n".to_vec()
+    );
+    // 48 bytes encode to exactly one full line.
+    let pem = encode("TEST", &[0xff; 49]);
+    let lines = pem.split(|&b| b == b'\n').collect::<Vec<_>>();
+    assert_eq!(lines[1], &[b'/'; 64][..]);
+    assert_eq!(lines[2], b"/w==");
+}
@@ -0,0 +1,66 @@
+//! A bounded cache of client sessions, keyed by the domain they were established with.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// A thread-safe cache which evicts its least recently used entry once full.
+pub struct SessionCache<T> {
+    // Ordered from most to least recently used.
+    entries: Mutex<VecDeque<(String, T)>>,
+    capacity: usize,
+}
+
+impl<T: Clone> SessionCache<T> {
+    pub fn new(capacity: usize) -> SessionCache<T> {
+        SessionCache {
+            entries: Mutex::new(VecDeque::new()),
+            capacity,
+        }
+    }
+
+    pub fn get(&self, domain: &str) -> Option<T> {
+        let mut entries = self.entries.lock().unwrap();
+        let pos = entries.iter().position(|e| e.0 == domain)?;
+        let entry = entries.remove(pos)?;
+        let session = entry.1.clone();
+        entries.push_front(entry);
+        Some(session)
+    }
+
+    pub fn insert(&self, domain: &str, session: T) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(pos) = entries.iter().position(|e| e.0 == domain) {
+            entries.remove(pos);
+        }
+        entries.truncate(self.capacity - 1);
+        entries.push_front((domain.to_owned(), session));
+    }
+}
+
+#[test]
+fn evicts_least_recently_used() {
+    let cache = SessionCache::new(2);
+    cache.insert("a", 1);
+    cache.insert("b", 2);
+    assert_eq!(cache.get("a"), Some(1));
+
+    cache.insert("c", 3);
+    assert_eq!(cache.get("b"), None);
+    assert_eq!(cache.get("a"), Some(1));
+    assert_eq!(cache.get("c"), Some(3));
+
+    cache.insert("a", 4);
+    assert_eq!(cache.get("a"), Some(4));
+    assert_eq!(cache.get("c"), Some(3));
+}
+
+#[test]
+fn zero_capacity() {
+    let cache = SessionCache::new(0);
+    cache.insert("a", 1);
+    assert_eq!(cache.get("a"), None);
+}
@@ -0,0 +1,181 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use TlsStream;
+
+type Shared<S> = Arc<Mutex<TlsStream<S>>>;
+
+fn lock<S>(stream: &Shared<S>) -> io::Result<MutexGuard<'_, TlsStream<S>>> {
+    // The session may have been left midway through a record by the panic.
+    stream.lock().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::Other,
+            "the other half of the stream panicked while using it",
+        )
+    })
+}
+
+// The underlying stream of a split `TlsStream`, which the backend writes to through a shared
+// reference while the read half reads from it.
+pub struct SharedStream<S> {
+    stream: Arc<S>,
+    write: fn(&S, &[u8]) -> io::Result<usize>,
+    flush: fn(&S) -> io::Result<()>,
+}
+
+impl<S> SharedStream<S> {
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        (self.write)(&self.stream, buf)
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        (self.flush)(&self.stream)
+    }
+}
+
+fn write_shared<S>(mut stream: &S, buf: &[u8]) -> io::Result<usize>
+where
+    for<'a> &'a S: Write,
+{
+    stream.write(buf)
+}
+
+fn flush_shared<S>(mut stream: &S) -> io::Result<()>
+where
+    for<'a> &'a S: Write,
+{
+    stream.flush()
+}
+
+/// The reading half of a `TlsStream`, created by `TlsStream::split`.
+pub struct ReadHalf<S> {
+    stream: Shared<S>,
+    transport: Arc<S>,
+}
+
+impl<S> ReadHalf<S> {
+    pub(crate) fn new(mut stream: TlsStream<S>) -> (ReadHalf<S>, WriteHalf<S>)
+    where
+        for<'a> &'a S: Read + Write,
+    {
+        let slot = stream.0.get_mut();
+        let transport = Arc::new(slot.take());
+        slot.shared = Some(SharedStream {
+            stream: transport.clone(),
+            write: write_shared::<S>,
+            flush: flush_shared::<S>,
+        });
+
+        let stream = Arc::new(Mutex::new(stream));
+        let read = ReadHalf {
+            stream: stream.clone(),
+            transport,
+        };
+        (read, WriteHalf(stream))
+    }
+
+    /// Returns `true` if both halves were split from the same stream.
+    pub fn is_pair_of(&self, other: &WriteHalf<S>) -> bool {
+        Arc::ptr_eq(&self.stream, &other.0)
+    }
+
+    /// Rejoins the halves into the stream they were split from.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the halves weren't split from the same stream, or if either half panicked while
+    /// using it.
+    pub fn unsplit(self, other: WriteHalf<S>) -> TlsStream<S> {
+        assert!(self.is_pair_of(&other), "unrelated halves");
+        drop(other);
+        drop(self.transport);
+        let mut stream = Arc::try_unwrap(self.stream)
+            .ok()
+            .expect("halves are still shared")
+            .into_inner()
+            .unwrap();
+
+        let slot = stream.0.get_mut();
+        let shared = slot.shared.take().expect("stream isn't split");
+        slot.stream = Arc::try_unwrap(shared.stream).ok();
+        stream
+    }
+}
+
+impl<S> fmt::Debug for ReadHalf<S> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ReadHalf").finish()
+    }
+}
+
+impl<S> Read for ReadHalf<S>
+where
+    S: io::Read + io::Write,
+    for<'a> &'a S: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            {
+                let mut stream = lock(&self.stream)?;
+                match stream.read(buf) {
+                    // The backend has processed everything read so far, and needs more.
+                    Err(ref e)
+                        if e.kind() == io::ErrorKind::WouldBlock
+                            && !stream.0.get_ref().write_blocked() => {}
+                    r => return r,
+                }
+            }
+
+            // The lock isn't held while waiting for the peer, so the write half can still send.
+            let mut data = [0; 16 * 1024];
+            let len = (&*self.transport).read(&mut data)?;
+
+            let mut stream = lock(&self.stream)?;
+            let slot = stream.0.get_mut();
+            slot.received.extend_from_slice(&data[..len]);
+            slot.received_eof = len == 0;
+        }
+    }
+}
+
+/// The writing half of a `TlsStream`, created by `TlsStream::split`.
+pub struct WriteHalf<S>(Shared<S>);
+
+impl<S> WriteHalf<S> {
+    /// Returns `true` if both halves were split from the same stream.
+    pub fn is_pair_of(&self, other: &ReadHalf<S>) -> bool {
+        other.is_pair_of(self)
+    }
+}
+
+impl<S: io::Read + io::Write> WriteHalf<S> {
+    /// Shuts down the TLS session, as `TlsStream::shutdown` does.
+    pub fn shutdown(&mut self) -> io::Result<()> {
+        lock(&self.0)?.shutdown()
+    }
+}
+
+impl<S> fmt::Debug for WriteHalf<S> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("WriteHalf").finish()
+    }
+}
+
+impl<S: io::Read + io::Write> Write for WriteHalf<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        lock(&self.0)?.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        lock(&self.0)?.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        lock(&self.0)?.flush()
+    }
+}
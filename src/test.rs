@@ -1,9 +1,23 @@
+#[cfg(target_os = "windows")]
+extern crate schannel;
+
+use std::error::Error as StdError;
 use std::fs;
-use std::io::{Read, Write};
+#[cfg(feature = "tokio")]
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::mem::MaybeUninit;
 use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
 use std::process::{Command, Stdio};
 use std::string::String;
+use std::sync::{mpsc, Arc, Mutex};
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll, Wake, Waker};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use super::*;
 
@@ -54,6 +68,111 @@ fn connect_no_root_certs() {
     assert!(builder.connect("google.com", s).is_err());
 }
 
+#[test]
+fn error_kind() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let mut cases = vec![(None, "localhost", ErrorKind::CertNotTrusted)];
+    // Secure Transport doesn't distinguish hostname mismatches from other trust failures.
+    if !cfg!(any(target_os = "macos", target_os = "ios")) {
+        cases.push((Some(root_ca), "foobar.com", ErrorKind::HostnameMismatch));
+    }
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let count = cases.len();
+    let j = thread::spawn(move || {
+        for _ in 0..count {
+            let socket = p!(listener.accept()).0;
+            let _ = acceptor.accept(socket);
+        }
+    });
+
+    for (root_ca, domain, kind) in cases {
+        let mut builder = TlsConnector::builder();
+        if let Some(root_ca) = root_ca {
+            builder.add_root_certificate(root_ca);
+        }
+        let connector = p!(builder.build());
+
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        match connector.connect(domain, socket) {
+            Err(HandshakeError::Failure { error: e, .. }) => assert_eq!(e.kind(), kind, "{}", e),
+            Err(HandshakeError::WouldBlock(_)) => panic!("unexpected WouldBlock"),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+
+    p!(j.join());
+}
+
+#[test]
+fn error_from_io() {
+    let e = Error::from(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+    assert_eq!(e.kind(), ErrorKind::Io);
+    assert_eq!(e.to_string(), "timed out");
+    let source = e.source().unwrap().downcast_ref::<io::Error>().unwrap();
+    assert_eq!(source.kind(), io::ErrorKind::TimedOut);
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn handshake_failure_returns_stream() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let _ = acceptor.accept(socket);
+    });
+
+    // The server's certificate isn't trusted.
+    let connector = p!(TlsConnector::new());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let local_addr = p!(socket.local_addr());
+    match connector.connect("localhost", socket) {
+        Err(HandshakeError::Failure {
+            stream: Some(socket),
+            ..
+        }) => assert_eq!(p!(socket.local_addr()), local_addr),
+        Err(HandshakeError::Failure { stream: None, .. }) => panic!("stream not returned"),
+        Err(HandshakeError::WouldBlock(_)) => panic!("unexpected WouldBlock"),
+        Ok(_) => panic!("expected an error"),
+    }
+
+    p!(j.join());
+}
+
+#[test]
+fn error_raw_os_error() {
+    let path = Path::new("does-not-exist.pem");
+    let expected = fs::read(path).unwrap_err().raw_os_error();
+    assert!(expected.is_some());
+
+    let err = match Identity::from_pkcs8_files(path, path) {
+        Ok(_) => panic!("expected an error"),
+        Err(e) => e,
+    };
+    assert_eq!(err.raw_os_error(), expected);
+    assert_eq!(err.kind(), ErrorKind::Io);
+}
+
 #[test]
 fn server_no_root_certs() {
     let keys = test_cert_gen::keys();
@@ -141,7 +260,7 @@ fn certificate_from_pem() {
     let keys = test_cert_gen::keys();
 
     let der_path = dir.path().join("cert.der");
-    fs::write(&der_path, &keys.client.ca.get_der()).unwrap();
+    fs::write(&der_path, keys.client.ca.get_der()).unwrap();
     let output = Command::new("openssl")
         .arg("x509")
         .arg("-in")
@@ -159,252 +278,4386 @@ fn certificate_from_pem() {
 }
 
 #[test]
-fn peer_certificate() {
+fn certificate_names() {
     let keys = test_cert_gen::keys();
 
-    let identity = p!(Identity::from_pkcs12(
-        &keys.server.cert_and_key_pkcs12.pkcs12.0,
-        &keys.server.cert_and_key_pkcs12.password
+    let cert = p!(Certificate::from_der(
+        keys.server.cert_and_key.cert.get_der()
     ));
-    let builder = p!(TlsAcceptor::new(identity));
+    assert_eq!(
+        p!(cert.subject_name()),
+        "CN=localhost,O=ACME Service,L=Provo,ST=Utah,C=US"
+    );
+    assert_eq!(
+        p!(cert.issuer_name()),
+        "CN=my.ca,O=Dis,L=Sprintfield,ST=Denial,C=US"
+    );
+}
 
-    let listener = p!(TcpListener::bind("0.0.0.0:0"));
-    let port = p!(listener.local_addr()).port();
+#[test]
+fn certificate_validity() {
+    let keys = test_cert_gen::keys();
 
-    let j = thread::spawn(move || {
-        let socket = p!(listener.accept()).0;
-        let socket = p!(builder.accept(socket));
-        assert!(socket.peer_certificate().unwrap().is_none());
-    });
+    let cert = p!(Certificate::from_der(
+        keys.server.cert_and_key.cert.get_der()
+    ));
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    assert!(p!(cert.not_before()) <= now);
+    assert!(p!(cert.not_after()) > now);
+}
 
-    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+#[test]
+fn certificate_serial_number() {
+    let cert = p!(Certificate::from_pem(include_bytes!(
+        "../test/serial/cert.pem"
+    )));
+    assert_eq!(
+        p!(cert.serial_number()),
+        [0xa1, 0xb2, 0xc3, 0xd4, 0xe5, 0xf6, 0x07, 0x18]
+    );
 
-    let socket = p!(TcpStream::connect(("localhost", port)));
-    let builder = p!(TlsConnector::builder()
-        .add_root_certificate(root_ca)
-        .build());
-    let socket = p!(builder.connect("localhost", socket));
+    let cert = p!(Certificate::from_pem(include_bytes!(
+        "../test/expired/cert.pem"
+    )));
+    assert_eq!(p!(cert.serial_number()), [0x01]);
+}
 
-    let cert = socket.peer_certificate().unwrap().unwrap();
+#[test]
+fn certificate_public_key_info() {
+    let keys = test_cert_gen::keys();
+    let cert = p!(Certificate::from_der(
+        keys.server.cert_and_key.cert.get_der()
+    ));
     assert_eq!(
-        cert.to_der().unwrap(),
+        p!(cert.public_key_info()),
+        PublicKeyInfo::Rsa { bits: 2048 }
+    );
+
+    let cases = [
+        (
+            &include_bytes!("../test/public-key/rsa-4096.pem")[..],
+            PublicKeyInfo::Rsa { bits: 4096 },
+        ),
+        (
+            include_bytes!("../test/public-key/ecdsa-p256.pem"),
+            PublicKeyInfo::Ecdsa {
+                curve: NamedGroup::Secp256r1,
+            },
+        ),
+        (
+            include_bytes!("../test/public-key/ecdsa-p384.pem"),
+            PublicKeyInfo::Ecdsa {
+                curve: NamedGroup::Secp384r1,
+            },
+        ),
+        (
+            include_bytes!("../test/public-key/ed25519.pem"),
+            PublicKeyInfo::Ed25519,
+        ),
+    ];
+    for &(pem, info) in &cases {
+        let cert = p!(Certificate::from_pem(pem));
+        assert_eq!(p!(cert.public_key_info()), info);
+    }
+}
+
+#[test]
+fn verify_chain_offline() {
+    let keys = test_cert_gen::keys();
+    let leaf = p!(Certificate::from_der(
+        keys.server.cert_and_key.cert.get_der()
+    ));
+    let ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+
+    let chain = [leaf];
+    let roots = [ca];
+    p!(verify_certificate_chain(
+        &chain,
+        &roots,
+        Some("localhost"),
+        None
+    ));
+    let err = verify_certificate_chain(&chain, &[], Some("localhost"), None).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::CertNotTrusted, "{}", err);
+    assert!(verify_certificate_chain(&[], &roots, None, None).is_err());
+}
+
+// Secure Transport can only validate chains as of the current time.
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn verify_chain_offline_at_time() {
+    let ca = p!(Certificate::from_pem(include_bytes!(
+        "../test/expired/ca.pem"
+    )));
+    let chain = [p!(Certificate::from_pem(include_bytes!(
+        "../test/expired/cert.pem"
+    )))];
+    let roots = [ca];
+    // 2020-01-15, while the certificate was valid.
+    let when = UNIX_EPOCH + Duration::from_secs(1_579_046_400);
+
+    p!(verify_certificate_chain(
+        &chain,
+        &roots,
+        Some("localhost"),
+        Some(when)
+    ));
+    p!(verify_certificate_chain(&chain, &roots, None, Some(when)));
+
+    let err = verify_certificate_chain(&chain, &roots, Some("localhost"), None).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::CertExpired, "{}", err);
+
+    let err =
+        verify_certificate_chain(&chain, &roots, Some("example.com"), Some(when)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::HostnameMismatch, "{}", err);
+}
+
+#[test]
+fn certificate_fingerprint() {
+    let keys = test_cert_gen::keys();
+
+    let cert = p!(Certificate::from_der(
         keys.server.cert_and_key.cert.get_der()
+    ));
+    let der = keys.server.cert_and_key.cert.get_der();
+    assert_eq!(p!(cert.fingerprint(HashAlgo::Sha256)), sha256(der));
+    for &(algo, len) in &[
+        (HashAlgo::Sha1, 20),
+        (HashAlgo::Sha384, 48),
+        (HashAlgo::Sha512, 64),
+    ] {
+        assert_eq!(p!(cert.fingerprint(algo)).len(), len);
+    }
+}
+
+#[test]
+fn certificate_eq_hash() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashSet;
+    use std::hash::{Hash, Hasher};
+
+    let hash = |cert: &Certificate| {
+        let mut hasher = DefaultHasher::new();
+        cert.hash(&mut hasher);
+        hasher.finish()
+    };
+
+    let pem = include_bytes!("../test/rotated/cert.pem");
+    let a = p!(Certificate::from_pem(pem));
+    let b = p!(Certificate::from_pem(pem));
+    assert!(a == b);
+    assert_eq!(hash(&a), hash(&b));
+
+    // A reissue of the same certificate is a different certificate.
+    let rotated = p!(Certificate::from_pem(include_bytes!(
+        "../test/rotated/rotated.pem"
+    )));
+    assert!(a != rotated);
+
+    let certs = [a, b, rotated].iter().cloned().collect::<HashSet<_>>();
+    assert_eq!(certs.len(), 2);
+}
+
+#[test]
+fn certificate_subject_alt_names() {
+    let dir = tempfile::tempdir().unwrap();
+    let self_signed = |extensions: &[&str]| {
+        let output = Command::new("openssl")
+            .args([
+                "req",
+                "-x509",
+                "-newkey",
+                "rsa:2048",
+                "-nodes",
+                "-subj",
+                "/CN=SAN test",
+            ])
+            .args(extensions)
+            .args(["-days", "1", "-keyout", "key.pem", "-out", "cert.pem"])
+            .current_dir(dir.path())
+            .stderr(Stdio::piped())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        p!(Certificate::from_pem(&p!(fs::read(
+            dir.path().join("cert.pem")
+        ))))
+    };
+
+    let cert = self_signed(&[
+        "-addext",
+        "subjectAltName = DNS:example.com, IP:127.0.0.1, IP:::1, \
+         email:admin@example.com, URI:https://example.com/, DNS:*.example.com",
+    ]);
+    assert_eq!(
+        p!(cert.subject_alt_names()),
+        vec![
+            SubjectAltName::DnsName("example.com".to_string()),
+            SubjectAltName::IpAddress("127.0.0.1".parse().unwrap()),
+            SubjectAltName::IpAddress("::1".parse().unwrap()),
+            SubjectAltName::Email("admin@example.com".to_string()),
+            SubjectAltName::Uri("https://example.com/".to_string()),
+            SubjectAltName::DnsName("*.example.com".to_string()),
+        ]
     );
 
-    p!(j.join());
+    let cert = self_signed(&[]);
+    assert_eq!(p!(cert.subject_alt_names()), vec![]);
 }
 
 #[test]
-fn server_tls11_only() {
+#[cfg(target_os = "windows")]
+fn root_store() {
     let keys = test_cert_gen::keys();
 
     let identity = p!(Identity::from_pkcs12(
         &keys.server.cert_and_key_pkcs12.pkcs12.0,
         &keys.server.cert_and_key_pkcs12.password
     ));
-    let builder = p!(TlsAcceptor::builder(identity)
-        .min_protocol_version(Some(Protocol::Tlsv12))
-        .max_protocol_version(Some(Protocol::Tlsv12))
-        .build());
+    let acceptor = p!(TlsAcceptor::new(identity));
 
     let listener = p!(TcpListener::bind("0.0.0.0:0"));
     let port = p!(listener.local_addr()).port();
 
     let j = thread::spawn(move || {
         let socket = p!(listener.accept()).0;
-        let mut socket = p!(builder.accept(socket));
-
-        let mut buf = [0; 5];
-        p!(socket.read_exact(&mut buf));
-        assert_eq!(&buf, b"hello");
-
-        p!(socket.write_all(b"world"));
+        let mut socket = p!(acceptor.accept(socket));
+        p!(socket.write_all(b"hello"));
     });
 
-    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+    let mut store = p!(schannel::cert_store::Memory::new()).into_store();
+    let root_ca = p!(schannel::cert_context::CertContext::new(
+        keys.client.ca.get_der()
+    ));
+    p!(store.add_cert(&root_ca, schannel::cert_store::CertAdd::Always));
 
+    let connector = p!(TlsConnector::builder().set_root_store(store).build());
     let socket = p!(TcpStream::connect(("localhost", port)));
-    let builder = p!(TlsConnector::builder()
-        .add_root_certificate(root_ca)
-        .min_protocol_version(Some(Protocol::Tlsv12))
-        .max_protocol_version(Some(Protocol::Tlsv12))
-        .build());
-    let mut socket = p!(builder.connect("localhost", socket));
-
-    p!(socket.write_all(b"hello"));
-    let mut buf = vec![];
-    p!(socket.read_to_end(&mut buf));
-    assert_eq!(buf, b"world");
+    let mut socket = p!(connector.connect("localhost", socket));
+    p!(socket.read_exact(&mut [0; 5]));
 
     p!(j.join());
 }
 
+// The test CA is imported into the current user's personal store, which doesn't require
+// confirmation, and removed again afterwards.
 #[test]
-fn server_no_shared_protocol() {
+#[cfg(target_os = "windows")]
+fn roots_from_system_store() {
     let keys = test_cert_gen::keys();
 
     let identity = p!(Identity::from_pkcs12(
         &keys.server.cert_and_key_pkcs12.pkcs12.0,
         &keys.server.cert_and_key_pkcs12.password
     ));
-    let builder = p!(TlsAcceptor::builder(identity)
-        .min_protocol_version(Some(Protocol::Tlsv12))
-        .build());
+    let acceptor = p!(TlsAcceptor::new(identity));
 
     let listener = p!(TcpListener::bind("0.0.0.0:0"));
     let port = p!(listener.local_addr()).port();
 
     let j = thread::spawn(move || {
         let socket = p!(listener.accept()).0;
-        assert!(builder.accept(socket).is_err());
+        let mut socket = p!(acceptor.accept(socket));
+        p!(socket.write_all(b"hello"));
     });
 
-    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+    let mut store = p!(schannel::cert_store::CertStore::open_current_user("My"));
+    let root_ca = p!(schannel::cert_context::CertContext::new(
+        keys.client.ca.get_der()
+    ));
+    let root_ca = p!(store.add_cert(&root_ca, schannel::cert_store::CertAdd::ReplaceExisting));
 
+    let connector = TlsConnector::builder()
+        .add_roots_from_system_store(SystemStore::My)
+        .build();
+    p!(root_ca.delete());
+    let connector = p!(connector);
     let socket = p!(TcpStream::connect(("localhost", port)));
-    let builder = p!(TlsConnector::builder()
-        .add_root_certificate(root_ca)
-        .min_protocol_version(Some(Protocol::Tlsv11))
-        .max_protocol_version(Some(Protocol::Tlsv11))
-        .build());
-    assert!(builder.connect("localhost", socket).is_err());
+    let mut socket = p!(connector.connect("localhost", socket));
+    p!(socket.read_exact(&mut [0; 5]));
 
     p!(j.join());
 }
 
 #[test]
-fn server_untrusted() {
+fn certificate_validation_result() {
     let keys = test_cert_gen::keys();
 
     let identity = p!(Identity::from_pkcs12(
         &keys.server.cert_and_key_pkcs12.pkcs12.0,
         &keys.server.cert_and_key_pkcs12.password
     ));
-    let builder = p!(TlsAcceptor::new(identity));
+    let acceptor = p!(TlsAcceptor::new(identity));
 
     let listener = p!(TcpListener::bind("0.0.0.0:0"));
     let port = p!(listener.local_addr()).port();
 
     let j = thread::spawn(move || {
-        let socket = p!(listener.accept()).0;
-        // FIXME should assert error
-        // https://github.com/steffengy/schannel-rs/issues/20
-        let _ = builder.accept(socket);
+        for _ in 0..2 {
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(acceptor.accept(socket));
+            p!(socket.write_all(b"hello"));
+        }
     });
 
+    let mut builder = TlsConnector::builder();
+    builder.danger_accept_invalid_certs(true);
+
+    let connector = p!(builder.build());
     let socket = p!(TcpStream::connect(("localhost", port)));
-    let builder = p!(TlsConnector::new());
-    builder.connect("localhost", socket).unwrap_err();
+    let mut socket = p!(connector.connect("localhost", socket));
+    p!(socket.read_exact(&mut [0; 5]));
+    assert!(socket.certificate_validation_result().is_some());
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(builder.add_root_certificate(root_ca).build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let mut socket = p!(connector.connect("localhost", socket));
+    p!(socket.read_exact(&mut [0; 5]));
+    assert!(socket.certificate_validation_result().is_none());
 
     p!(j.join());
 }
 
 #[test]
-fn server_untrusted_unverified() {
+fn connect_owned() {
     let keys = test_cert_gen::keys();
 
     let identity = p!(Identity::from_pkcs12(
         &keys.server.cert_and_key_pkcs12.pkcs12.0,
         &keys.server.cert_and_key_pkcs12.password
     ));
-    let builder = p!(TlsAcceptor::new(identity));
+    let acceptor = p!(TlsAcceptor::new(identity));
 
     let listener = p!(TcpListener::bind("0.0.0.0:0"));
     let port = p!(listener.local_addr()).port();
 
     let j = thread::spawn(move || {
         let socket = p!(listener.accept()).0;
-        let mut socket = p!(builder.accept(socket));
-
-        let mut buf = [0; 5];
-        p!(socket.read_exact(&mut buf));
-        assert_eq!(&buf, b"hello");
-
-        p!(socket.write_all(b"world"));
+        let mut socket = p!(acceptor.accept(socket));
+        p!(socket.write_all(b"hello"));
     });
 
-    let socket = p!(TcpStream::connect(("localhost", port)));
-    let builder = p!(TlsConnector::builder()
-        .danger_accept_invalid_certs(true)
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
         .build());
-    let mut socket = p!(builder.connect("localhost", socket));
-
-    p!(socket.write_all(b"hello"));
-    let mut buf = vec![];
-    p!(socket.read_to_end(&mut buf));
-    assert_eq!(buf, b"world");
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let mut socket = p!(connector.connect_owned("localhost".to_string(), socket));
+    let mut buf = [0; 5];
+    p!(socket.read_exact(&mut buf));
+    assert_eq!(&buf, b"hello");
 
     p!(j.join());
 }
 
 #[test]
-fn import_same_identity_multiple_times() {
-    let keys = test_cert_gen::keys();
+fn connect_blocking() {
+    #[derive(Debug)]
+    struct InterruptedOnce {
+        stream: TcpStream,
+        interrupted: bool,
+    }
 
-    let _ = p!(Identity::from_pkcs12(
-        &keys.server.cert_and_key_pkcs12.pkcs12.0,
-        &keys.server.cert_and_key_pkcs12.password
-    ));
-    let _ = p!(Identity::from_pkcs12(
-        &keys.server.cert_and_key_pkcs12.pkcs12.0,
-        &keys.server.cert_and_key_pkcs12.password
-    ));
+    impl Read for InterruptedOnce {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.interrupted {
+                self.interrupted = true;
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.stream.read(buf)
+        }
+    }
 
-    let cert = keys.server.cert_and_key.cert.to_pem().into_bytes();
-    let key = rsa_to_pkcs8(&keys.server.cert_and_key.key.to_pem_incorrect()).into_bytes();
-    let _ = p!(Identity::from_pkcs8(&cert, &key));
-    let _ = p!(Identity::from_pkcs8(&cert, &key));
-}
+    impl Write for InterruptedOnce {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.stream.write(buf)
+        }
 
-#[test]
-fn from_pkcs8_rejects_rsa_key() {
-    let keys = test_cert_gen::keys();
-    let cert = keys.server.cert_and_key.cert.to_pem().into_bytes();
-    let rsa_key = keys.server.cert_and_key.key.to_pem_incorrect();
-    assert!(Identity::from_pkcs8(&cert, rsa_key.as_bytes()).is_err());
-    let pkcs8_key = rsa_to_pkcs8(&rsa_key);
-    assert!(Identity::from_pkcs8(&cert, pkcs8_key.as_bytes()).is_ok());
-}
+        fn flush(&mut self) -> io::Result<()> {
+            self.stream.flush()
+        }
+    }
 
-#[test]
-fn shutdown() {
     let keys = test_cert_gen::keys();
 
     let identity = p!(Identity::from_pkcs12(
         &keys.server.cert_and_key_pkcs12.pkcs12.0,
         &keys.server.cert_and_key_pkcs12.password
     ));
-    let builder = p!(TlsAcceptor::new(identity));
+    let acceptor = p!(TlsAcceptor::new(identity));
 
     let listener = p!(TcpListener::bind("0.0.0.0:0"));
     let port = p!(listener.local_addr()).port();
 
     let j = thread::spawn(move || {
         let socket = p!(listener.accept()).0;
-        let mut socket = p!(builder.accept(socket));
-
-        let mut buf = [0; 5];
-        p!(socket.read_exact(&mut buf));
-        assert_eq!(&buf, b"hello");
-
-        assert_eq!(p!(socket.read(&mut buf)), 0);
-        p!(socket.shutdown());
+        let mut socket = p!(acceptor.accept(socket));
+        p!(socket.write_all(b"hello"));
     });
 
-    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
-
-    let socket = p!(TcpStream::connect(("localhost", port)));
-    let builder = p!(TlsConnector::builder()
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(TlsConnector::builder()
         .add_root_certificate(root_ca)
         .build());
-    let mut socket = p!(builder.connect("localhost", socket));
-
-    p!(socket.write_all(b"hello"));
-    p!(socket.shutdown());
+    let stream = InterruptedOnce {
+        stream: p!(TcpStream::connect(("localhost", port))),
+        interrupted: false,
+    };
+    let mut socket = p!(connector.connect_blocking("localhost", stream));
+    assert!(socket.get_ref().interrupted);
+    let mut buf = [0; 5];
+    p!(socket.read_exact(&mut buf));
+    assert_eq!(&buf, b"hello");
 
     p!(j.join());
 }
 
+#[test]
+fn tls_in_tls() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let outer = p!(acceptor.accept(socket));
+        let mut inner = p!(acceptor.accept(outer));
+        // Larger than a record, so the inner records span several outer reads.
+        let mut buf = vec![0; 40000];
+        p!(inner.read_exact(&mut buf));
+        assert!(buf.iter().all(|&b| b == 1));
+        p!(inner.write_all(&[2; 40000]));
+        p!(inner.flush());
+        let mut buf = [0; 1];
+        p!(inner.read_exact(&mut buf));
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let outer = p!(connector.connect("localhost", socket));
+    let mut inner = p!(connector.connect("localhost", outer));
+    p!(inner.write_all(&[1; 40000]));
+    p!(inner.flush());
+    let mut buf = vec![0; 40000];
+    p!(inner.read_exact(&mut buf));
+    assert!(buf.iter().all(|&b| b == 2));
+    p!(inner.write_all(&[3]));
+
+    p!(j.join());
+}
+
+#[test]
+fn refresh_system_roots() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(acceptor.accept(socket));
+        p!(socket.write_all(b"hello"));
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    p!(TlsConnector::new());
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .refresh_system_roots()
+        .build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let mut socket = p!(connector.connect("localhost", socket));
+    p!(socket.read_exact(&mut [0; 5]));
+
+    p!(j.join());
+}
+
+#[test]
+fn peer_certificate() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let socket = p!(builder.accept(socket));
+        assert!(socket.peer_certificate().unwrap().is_none());
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let socket = p!(builder.connect("localhost", socket));
+
+    let cert = socket.peer_certificate().unwrap().unwrap();
+    assert_eq!(
+        cert.to_der().unwrap(),
+        keys.server.cert_and_key.cert.get_der()
+    );
+
+    p!(j.join());
+}
+
+#[test]
+fn peer_certificate_chain() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let socket = p!(builder.accept(socket));
+        assert!(p!(socket.peer_certificate_chain()).is_empty());
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let socket = p!(builder.connect("localhost", socket));
+
+    let chain = p!(socket.peer_certificate_chain());
+    assert!(!chain.is_empty());
+    assert_eq!(
+        chain[0].to_der().unwrap(),
+        keys.server.cert_and_key.cert.get_der()
+    );
+
+    p!(j.join());
+}
+
+#[test]
+fn verify_callback() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        for _ in 0..3 {
+            let socket = p!(listener.accept()).0;
+            if let Ok(mut socket) = builder.accept(socket) {
+                let _ = socket.write_all(b"hello");
+            }
+        }
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+    let leaf = keys.server.cert_and_key.cert.get_der().to_vec();
+
+    for &trust_root in &[false, true] {
+        let seen = Arc::new(Mutex::new(None));
+        let mut builder = TlsConnector::builder();
+        if trust_root {
+            builder.add_root_certificate(root_ca.clone());
+        }
+        let builder = {
+            let seen = seen.clone();
+            p!(builder
+                .verify_callback(move |chain| {
+                    let der = chain.leaf().unwrap().to_der().unwrap();
+                    *seen.lock().unwrap() = Some((chain.is_verified(), der));
+                    Ok(())
+                })
+                .build())
+        };
+
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let mut socket = p!(builder.connect("localhost", socket));
+        let mut buf = vec![];
+        p!(socket.read_to_end(&mut buf));
+        assert_eq!(buf, b"hello");
+
+        let seen = seen.lock().unwrap().take();
+        assert_eq!(seen, Some((trust_root, leaf.clone())));
+    }
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .verify_callback(|_| {
+            Err(Error::from(io::Error::new(
+                io::ErrorKind::Other,
+                "rejected",
+            )))
+        })
+        .build());
+    assert!(builder.connect("localhost", socket).is_err());
+
+    p!(j.join());
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn ignore_validation_errors() {
+    let ca = p!(Certificate::from_pem(include_bytes!(
+        "../test/expired/ca.pem"
+    )));
+    let identity = p!(Identity::from_pkcs8(
+        include_bytes!("../test/expired/cert.pem"),
+        include_bytes!("../test/expired/key.pem"),
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+    let listener = p!(TcpListener::bind("127.0.0.1:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let cases = [
+        (true, vec![], false),
+        (true, vec![ValidationError::Expired], true),
+        (false, vec![ValidationError::Expired], false),
+        (
+            false,
+            vec![ValidationError::Expired, ValidationError::UntrustedRoot],
+            true,
+        ),
+    ];
+
+    let j = thread::spawn(move || {
+        for _ in 0..4 {
+            let socket = p!(listener.accept()).0;
+            if let Ok(mut socket) = acceptor.accept(socket) {
+                p!(socket.write_all(b"hello"));
+            }
+        }
+    });
+
+    for &(trust_ca, ref ignored, accepted) in &cases {
+        let mut builder = TlsConnector::builder();
+        if trust_ca {
+            builder.add_root_certificate(ca.clone());
+        }
+        let connector = p!(builder.ignore_validation_errors(ignored).build());
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        match connector.connect("localhost", socket) {
+            Ok(mut socket) => {
+                assert!(accepted, "{:?} accepted", ignored);
+                p!(socket.read_exact(&mut [0; 5]));
+            }
+            Err(e) => assert!(!accepted, "{:?} rejected: {}", ignored, e),
+        }
+    }
+
+    p!(j.join());
+}
+
+#[test]
+fn rebuilt_acceptor_presents_new_identity() {
+    let keys = test_cert_gen::keys();
+    let old = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let new = p!(Identity::from_pkcs8(
+        include_bytes!("../test/expired/cert.pem"),
+        include_bytes!("../test/expired/key.pem"),
+    ));
+    let new_cert = p!(Certificate::from_pem(include_bytes!(
+        "../test/expired/cert.pem"
+    )));
+
+    let listener = p!(TcpListener::bind("127.0.0.1:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        for identity in [old, new] {
+            let acceptor = p!(TlsAcceptor::new(identity));
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(acceptor.accept(socket));
+            p!(socket.write_all(b"hello"));
+        }
+    });
+
+    // Reuse one connector so a cached session can't mask the rotation.
+    let connector = p!(TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .build());
+    let mut presented = vec![];
+    for _ in 0..2 {
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let mut socket = p!(connector.connect("localhost", socket));
+        p!(socket.read_exact(&mut [0; 5]));
+        presented.push(p!(p!(socket.peer_certificate()).unwrap().to_der()));
+    }
+
+    assert_eq!(presented[0], keys.server.cert_and_key.cert.get_der());
+    assert_eq!(presented[1], p!(new_cert.to_der()));
+
+    p!(j.join());
+}
+
+#[test]
+fn on_verification_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = Command::new("openssl")
+        .args([
+            "req",
+            "-x509",
+            "-newkey",
+            "rsa:2048",
+            "-nodes",
+            "-subj",
+            "/CN=localhost",
+            "-addext",
+            "subjectAltName = DNS:localhost",
+            "-days",
+            "1",
+            "-keyout",
+            "key.pem",
+            "-out",
+            "cert.pem",
+        ])
+        .current_dir(dir.path())
+        .stderr(Stdio::piped())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let cert = fs::read(dir.path().join("cert.pem")).unwrap();
+    let key = fs::read(dir.path().join("key.pem")).unwrap();
+    let self_signed = p!(Certificate::from_pem(&cert));
+
+    let acceptor = p!(TlsAcceptor::new(p!(Identity::from_pkcs8(&cert, &key))));
+    let listener = p!(TcpListener::bind("127.0.0.1:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        for _ in 0..2 {
+            let socket = p!(listener.accept()).0;
+            if let Ok(mut socket) = acceptor.accept(socket) {
+                p!(socket.write_all(b"hello"));
+            }
+        }
+    });
+
+    for &trust_self_signed in &[false, true] {
+        let seen = Arc::new(Mutex::new(None));
+        let mut builder = TlsConnector::builder();
+        if trust_self_signed {
+            builder.add_root_certificate(self_signed.clone());
+        }
+        let connector = {
+            let seen = seen.clone();
+            p!(builder
+                .on_verification_failure(move |chain| {
+                    let chain = chain
+                        .iter()
+                        .map(|cert| cert.to_der().unwrap())
+                        .collect::<Vec<_>>();
+                    *seen.lock().unwrap() = Some(chain);
+                })
+                .build())
+        };
+
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let result = connector.connect("localhost", socket);
+        let seen = seen.lock().unwrap().take();
+        if trust_self_signed {
+            let mut socket = p!(result);
+            let mut buf = vec![];
+            p!(socket.read_to_end(&mut buf));
+            assert_eq!(buf, b"hello");
+            assert_eq!(seen, None);
+        } else {
+            assert!(result.is_err());
+            assert_eq!(seen, Some(vec![p!(self_signed.to_der())]));
+        }
+    }
+
+    p!(j.join());
+}
+
+#[test]
+fn supported_cipher_suites() {
+    let suites = p!(super::supported_cipher_suites());
+    assert!(!suites.is_empty());
+    assert!(
+        suites.iter().any(|suite| suite.name().contains("_WITH_")
+            && (suite.name().contains("_GCM_") || suite.name().contains("_POLY1305_"))),
+        "{:?}",
+        suites
+    );
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn negotiated_cipher_suite() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let socket = p!(builder.accept(socket));
+        p!(socket.negotiated_cipher_suite())
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let socket = p!(builder.connect("localhost", socket));
+
+    let suite = p!(socket.negotiated_cipher_suite()).unwrap();
+    assert!(suite.name().starts_with("TLS_"), "{}", suite.name());
+    assert_ne!(suite.id(), 0);
+
+    assert_eq!(Some(suite), p!(j.join()));
+}
+
+#[test]
+fn connector_shared_across_threads() {
+    const THREADS: usize = 16;
+
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let clients = (0..THREADS)
+            .map(|_| {
+                let socket = p!(listener.accept()).0;
+                let acceptor = acceptor.clone();
+                thread::spawn(move || {
+                    let mut socket = p!(acceptor.accept(socket));
+                    p!(socket.write_all(b"hello"));
+                })
+            })
+            .collect::<Vec<_>>();
+        for client in clients {
+            p!(client.join());
+        }
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+
+    let threads = (0..THREADS)
+        .map(|_| {
+            let connector = connector.clone();
+            thread::spawn(move || {
+                let socket = p!(TcpStream::connect(("localhost", port)));
+                let mut socket = p!(connector.connect("localhost", socket));
+                let mut buf = vec![];
+                p!(socket.read_to_end(&mut buf));
+                assert_eq!(buf, b"hello");
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        p!(thread.join());
+    }
+
+    p!(j.join());
+}
+
+#[test]
+fn ciphers() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        p!(socket.write_all(b"hello"));
+    });
+
+    let suite = CipherSuite::from_name("TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384").unwrap();
+    assert_eq!(suite.id(), 0xC030);
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca.clone())
+        .max_protocol_version(Some(Protocol::Tlsv12))
+        .ciphers(&[suite])
+        .build());
+    let mut socket = p!(builder.connect("localhost", socket));
+
+    // Not every implementation can report the negotiated suite.
+    if let Ok(Some(suite)) = socket.negotiated_cipher_suite() {
+        assert!(suite.name().contains("AES_256"), "{}", suite.name());
+    }
+    let mut buf = vec![];
+    p!(socket.read_to_end(&mut buf));
+    assert_eq!(buf, b"hello");
+
+    p!(j.join());
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let mut socket = p!(listener.accept()).0;
+        let mut buf = vec![];
+        let _ = socket.read_to_end(&mut buf);
+    });
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let result = TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .ciphers(&[CipherSuite::from_id(0xFFFF)])
+        .build();
+    if let Ok(builder) = result {
+        assert!(builder.connect("localhost", socket).is_err());
+    }
+
+    p!(j.join());
+}
+
+#[test]
+fn ciphers_key_exchange() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let static_rsa = CipherSuite::from_name("TLS_RSA_WITH_AES_128_CBC_SHA").unwrap();
+    let acceptor = p!(TlsAcceptor::builder(identity)
+        .max_protocol_version(Some(Protocol::Tlsv12))
+        .ciphers(&[static_rsa])
+        .build());
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        assert!(acceptor.accept(socket).is_err());
+    });
+
+    // The suites share a bulk cipher, but not a key exchange.
+    let ecdhe = CipherSuite::from_name("TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256").unwrap();
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .max_protocol_version(Some(Protocol::Tlsv12))
+        .ciphers(&[ecdhe])
+        .build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    assert!(connector.connect("localhost", socket).is_err());
+
+    p!(j.join());
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn signature_algorithms() {
+    let keys = test_cert_gen::keys();
+
+    let connect = |schemes: &[SignatureScheme]| {
+        let identity = p!(Identity::from_pkcs12(
+            &keys.server.cert_and_key_pkcs12.pkcs12.0,
+            &keys.server.cert_and_key_pkcs12.password
+        ));
+        let acceptor = p!(TlsAcceptor::new(identity));
+
+        let listener = p!(TcpListener::bind("0.0.0.0:0"));
+        let port = p!(listener.local_addr()).port();
+
+        let j = thread::spawn(move || {
+            let socket = p!(listener.accept()).0;
+            if let Ok(mut socket) = acceptor.accept(socket) {
+                p!(socket.write_all(b"hello"));
+            }
+        });
+
+        let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let connector = p!(TlsConnector::builder()
+            .add_root_certificate(root_ca)
+            .signature_algorithms(schemes)
+            .build());
+        let result = connector.connect("localhost", socket).map(|mut socket| {
+            let mut buf = vec![];
+            p!(socket.read_to_end(&mut buf));
+            assert_eq!(buf, b"hello");
+        });
+        p!(j.join());
+        result.is_ok()
+    };
+
+    // The server's key is RSA, so it can't sign with any of the ECDSA schemes.
+    assert!(connect(&[SignatureScheme::RsaPssRsaeSha256]));
+    assert!(!connect(&[
+        SignatureScheme::EcdsaSecp256r1Sha256,
+        SignatureScheme::EcdsaSecp384r1Sha384,
+    ]));
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn elliptic_curves() {
+    let keys = test_cert_gen::keys();
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("cert.pem"),
+        keys.server.cert_and_key.cert.to_pem(),
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("key.pem"),
+        rsa_to_pkcs8(&keys.server.cert_and_key.key.to_pem_incorrect()),
+    )
+    .unwrap();
+
+    let mut server = Command::new("openssl")
+        .args([
+            "s_server",
+            "-accept",
+            "127.0.0.1:0",
+            "-naccept",
+            "2",
+            "-www",
+            "-cert",
+            "cert.pem",
+            "-key",
+            "key.pem",
+            "-groups",
+            "P-384",
+        ])
+        .current_dir(dir.path())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdout = io::BufReader::new(server.stdout.take().unwrap());
+    let port = loop {
+        let mut line = String::new();
+        assert!(p!(io::BufRead::read_line(&mut stdout, &mut line)) > 0);
+        if line.starts_with("ACCEPT") {
+            break p!(line.trim().rsplit(':').next().unwrap().parse::<u16>());
+        }
+    };
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let mut builder = TlsConnector::builder();
+    builder.add_root_certificate(root_ca);
+
+    let connector = p!(builder
+        .elliptic_curves(&[NamedGroup::X25519, NamedGroup::Secp384r1])
+        .build());
+    let socket = p!(TcpStream::connect(("127.0.0.1", port)));
+    let mut socket = p!(connector.connect("localhost", socket));
+    assert_eq!(p!(socket.key_exchange_group()), Some(NamedGroup::Secp384r1));
+    p!(socket.shutdown());
+
+    let connector = p!(builder.elliptic_curves(&[NamedGroup::X25519]).build());
+    let socket = p!(TcpStream::connect(("127.0.0.1", port)));
+    assert!(connector.connect("localhost", socket).is_err());
+
+    p!(server.wait());
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn key_exchange_group() {
+    let keys = test_cert_gen::keys();
+
+    let negotiate = |groups: &[NamedGroup], suites: &[CipherSuite]| {
+        let identity = p!(Identity::from_pkcs12(
+            &keys.server.cert_and_key_pkcs12.pkcs12.0,
+            &keys.server.cert_and_key_pkcs12.password
+        ));
+        let acceptor = p!(TlsAcceptor::builder(identity)
+            .max_protocol_version(Some(Protocol::Tlsv12))
+            .ciphers(suites)
+            .build());
+
+        let listener = p!(TcpListener::bind("0.0.0.0:0"));
+        let port = p!(listener.local_addr()).port();
+
+        let j = thread::spawn(move || {
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(acceptor.accept(socket));
+            p!(socket.write_all(b"hello"));
+            p!(socket.key_exchange_group())
+        });
+
+        let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+        let connector = p!(TlsConnector::builder()
+            .add_root_certificate(root_ca)
+            .max_protocol_version(Some(Protocol::Tlsv12))
+            .ciphers(suites)
+            .elliptic_curves(groups)
+            .build());
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let mut socket = p!(connector.connect("localhost", socket));
+        p!(socket.read_exact(&mut [0; 5]));
+
+        let group = p!(socket.key_exchange_group());
+        assert_eq!(group, p!(j.join()));
+        group
+    };
+
+    assert_eq!(
+        negotiate(&[NamedGroup::Secp256r1], &[]),
+        Some(NamedGroup::Secp256r1)
+    );
+    assert_eq!(
+        negotiate(&[NamedGroup::X25519], &[]),
+        Some(NamedGroup::X25519)
+    );
+    let rsa = CipherSuite::from_name("TLS_RSA_WITH_AES_128_GCM_SHA256").unwrap();
+    assert_eq!(negotiate(&[], &[rsa]), None);
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn require_extended_master_secret() {
+    let mut server = Command::new("openssl")
+        .args([
+            "s_server",
+            "-accept",
+            "127.0.0.1:0",
+            "-naccept",
+            "2",
+            "-www",
+            "-cert",
+            "test/expired/cert.pem",
+            "-key",
+            "test/expired/key.pem",
+            "-tls1_2",
+            "-no_ems",
+        ])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdout = io::BufReader::new(server.stdout.take().unwrap());
+    let port = loop {
+        let mut line = String::new();
+        assert!(p!(io::BufRead::read_line(&mut stdout, &mut line)) > 0);
+        if line.starts_with("ACCEPT") {
+            break p!(line.trim().rsplit(':').next().unwrap().parse::<u16>());
+        }
+    };
+
+    let mut builder = TlsConnector::builder();
+    builder.danger_accept_invalid_certs(true);
+
+    let connector = p!(builder.build());
+    let socket = p!(TcpStream::connect(("127.0.0.1", port)));
+    let socket = p!(connector.connect("localhost", socket));
+    assert!(!p!(socket.extended_master_secret_used()));
+    drop(socket);
+
+    let connector = p!(builder.require_extended_master_secret(true).build());
+    let socket = p!(TcpStream::connect(("127.0.0.1", port)));
+    match connector.connect("localhost", socket) {
+        Err(HandshakeError::Failure { error, stream }) => {
+            assert_eq!(error.kind(), ErrorKind::Protocol);
+            assert!(error.to_string().contains("extended master secret"));
+            assert!(stream.is_some());
+        }
+        Err(HandshakeError::WouldBlock(_)) => panic!("unexpected WouldBlock"),
+        Ok(_) => panic!("connected without the extended master secret"),
+    }
+
+    p!(server.wait());
+
+    let identity = p!(Identity::from_pkcs8(
+        include_bytes!("../test/expired/cert.pem"),
+        include_bytes!("../test/expired/key.pem"),
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+    let listener = p!(TcpListener::bind("127.0.0.1:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        for _ in 0..2 {
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(acceptor.accept(socket));
+            assert!(p!(socket.extended_master_secret_used()));
+            p!(socket.write_all(b"hello"));
+        }
+    });
+
+    for &max in &[Protocol::Tlsv12, Protocol::Tlsv13] {
+        let connector = p!(builder.max_protocol_version(Some(max)).build());
+        let socket = p!(TcpStream::connect(("127.0.0.1", port)));
+        let mut socket = p!(connector.connect("localhost", socket));
+        p!(socket.read_exact(&mut [0; 5]));
+        assert!(p!(socket.extended_master_secret_used()));
+    }
+
+    p!(j.join());
+}
+
+// SChannel can't report the negotiated suite.
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn has_forward_secrecy() {
+    let keys = test_cert_gen::keys();
+
+    let negotiate = |name| {
+        let suites = [CipherSuite::from_name(name).unwrap()];
+        let identity = p!(Identity::from_pkcs12(
+            &keys.server.cert_and_key_pkcs12.pkcs12.0,
+            &keys.server.cert_and_key_pkcs12.password
+        ));
+        let acceptor = p!(TlsAcceptor::builder(identity)
+            .max_protocol_version(Some(Protocol::Tlsv12))
+            .ciphers(&suites)
+            .build());
+
+        let listener = p!(TcpListener::bind("0.0.0.0:0"));
+        let port = p!(listener.local_addr()).port();
+
+        let j = thread::spawn(move || {
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(acceptor.accept(socket));
+            p!(socket.write_all(b"hello"));
+        });
+
+        let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+        let connector = p!(TlsConnector::builder()
+            .add_root_certificate(root_ca)
+            .max_protocol_version(Some(Protocol::Tlsv12))
+            .ciphers(&suites)
+            .build());
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let mut socket = p!(connector.connect("localhost", socket));
+        p!(socket.read_exact(&mut [0; 5]));
+        p!(j.join());
+
+        p!(socket.has_forward_secrecy())
+    };
+
+    assert!(negotiate("TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"));
+    assert!(!negotiate("TLS_RSA_WITH_AES_128_GCM_SHA256"));
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn prefer_server_cipher_order() {
+    let keys = test_cert_gen::keys();
+    let aes_256 = CipherSuite::from_name("TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384").unwrap();
+    let aes_128 = CipherSuite::from_name("TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256").unwrap();
+
+    let negotiate = |prefer_server| {
+        let identity = p!(Identity::from_pkcs12(
+            &keys.server.cert_and_key_pkcs12.pkcs12.0,
+            &keys.server.cert_and_key_pkcs12.password
+        ));
+        let acceptor = p!(TlsAcceptor::builder(identity)
+            .max_protocol_version(Some(Protocol::Tlsv12))
+            .ciphers(&[aes_256.clone(), aes_128.clone()])
+            .prefer_server_cipher_order(prefer_server)
+            .build());
+
+        let listener = p!(TcpListener::bind("0.0.0.0:0"));
+        let port = p!(listener.local_addr()).port();
+
+        let j = thread::spawn(move || {
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(acceptor.accept(socket));
+            p!(socket.write_all(b"hello"));
+        });
+
+        let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+        let connector = p!(TlsConnector::builder()
+            .add_root_certificate(root_ca)
+            .max_protocol_version(Some(Protocol::Tlsv12))
+            .ciphers(&[aes_128.clone(), aes_256.clone()])
+            .build());
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let mut socket = p!(connector.connect("localhost", socket));
+        p!(socket.read_exact(&mut [0; 5]));
+        p!(j.join());
+
+        p!(socket.negotiated_cipher_suite()).unwrap()
+    };
+
+    assert_eq!(negotiate(true), aes_256);
+    assert_eq!(negotiate(false), aes_128);
+}
+
+#[test]
+fn tls_server_end_point() {
+    let dir = tempfile::tempdir().unwrap();
+
+    for algorithm in &["sha256", "sha384", "sha512"] {
+        let output = Command::new("openssl")
+            .args([
+                "req",
+                "-x509",
+                "-newkey",
+                "rsa:2048",
+                "-nodes",
+                &format!("-{}", algorithm),
+                "-subj",
+                "/CN=localhost",
+                "-days",
+                "1",
+                "-keyout",
+                "key.pem",
+                "-out",
+                "cert.pem",
+            ])
+            .current_dir(dir.path())
+            .stderr(Stdio::piped())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+
+        let cert = fs::read(dir.path().join("cert.pem")).unwrap();
+        let key = fs::read(dir.path().join("key.pem")).unwrap();
+        let identity = p!(Identity::from_pkcs8(&cert, &key));
+        let acceptor = p!(TlsAcceptor::new(identity));
+        let expected = digest(algorithm, &p!(p!(Certificate::from_pem(&cert)).to_der()));
+
+        let listener = p!(TcpListener::bind("0.0.0.0:0"));
+        let port = p!(listener.local_addr()).port();
+
+        let server_expected = expected.clone();
+        let j = thread::spawn(move || {
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(acceptor.accept(socket));
+            assert_eq!(p!(socket.tls_server_end_point()), Some(server_expected));
+            p!(socket.write_all(b"hello"));
+        });
+
+        let connector = p!(TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .build());
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let mut socket = p!(connector.connect("localhost", socket));
+        p!(socket.read_exact(&mut [0; 5]));
+        assert_eq!(p!(socket.tls_server_end_point()), Some(expected));
+
+        p!(j.join());
+    }
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn tls_unique() {
+    let keys = test_cert_gen::keys();
+
+    for &version in &[Protocol::Tlsv12, Protocol::Tlsv13] {
+        let identity = p!(Identity::from_pkcs12(
+            &keys.server.cert_and_key_pkcs12.pkcs12.0,
+            &keys.server.cert_and_key_pkcs12.password
+        ));
+        let acceptor = p!(TlsAcceptor::new(identity));
+
+        let listener = p!(TcpListener::bind("0.0.0.0:0"));
+        let port = p!(listener.local_addr()).port();
+
+        let j = thread::spawn(move || {
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(acceptor.accept(socket));
+            p!(socket.write_all(b"hello"));
+            p!(socket.tls_unique())
+        });
+
+        let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+        let connector = p!(TlsConnector::builder()
+            .add_root_certificate(root_ca)
+            .min_protocol_version(Some(version))
+            .max_protocol_version(Some(version))
+            .build());
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let mut socket = p!(connector.connect("localhost", socket));
+        p!(socket.read_exact(&mut [0; 5]));
+        let client = p!(socket.tls_unique());
+        let server = p!(j.join());
+
+        if version == Protocol::Tlsv13 {
+            assert_eq!(client, None);
+            assert_eq!(server, None);
+        } else {
+            assert_eq!(client.as_ref().map(|b| b.len()), Some(12));
+            assert_eq!(client, server);
+        }
+    }
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn export_keying_material() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let socket = p!(builder.accept(socket));
+        p!(socket.export_keying_material(b"EXPORTER-test", Some(b"context"), 32))
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let socket = p!(builder.connect("localhost", socket));
+
+    let material = p!(socket.export_keying_material(b"EXPORTER-test", Some(b"context"), 32));
+    assert_eq!(material.len(), 32);
+    assert_eq!(material, p!(j.join()));
+
+    let other = p!(socket.export_keying_material(b"EXPORTER-test", None, 32));
+    assert_ne!(material, other);
+    assert!(socket.export_keying_material(b"\xff", None, 32).is_err());
+}
+
+#[test]
+fn server_tls11_only() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::builder(identity)
+        .min_protocol_version(Some(Protocol::Tlsv12))
+        .max_protocol_version(Some(Protocol::Tlsv12))
+        .build());
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+
+        let mut buf = [0; 5];
+        p!(socket.read_exact(&mut buf));
+        assert_eq!(&buf, b"hello");
+
+        p!(socket.write_all(b"world"));
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .min_protocol_version(Some(Protocol::Tlsv12))
+        .max_protocol_version(Some(Protocol::Tlsv12))
+        .build());
+    let mut socket = p!(builder.connect("localhost", socket));
+
+    p!(socket.write_all(b"hello"));
+    let mut buf = vec![];
+    p!(socket.read_to_end(&mut buf));
+    assert_eq!(buf, b"world");
+
+    p!(j.join());
+}
+
+// Secure Transport can't serve TLS 1.3, and SChannel only can on recent Windows releases.
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn server_tls13_only() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::builder(identity)
+        .min_protocol_version(Some(Protocol::Tlsv13))
+        .max_protocol_version(Some(Protocol::Tlsv13))
+        .build());
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+
+        let mut buf = [0; 5];
+        p!(socket.read_exact(&mut buf));
+        assert_eq!(&buf, b"hello");
+
+        p!(socket.write_all(b"world"));
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .min_protocol_version(Some(Protocol::Tlsv13))
+        .max_protocol_version(Some(Protocol::Tlsv13))
+        .build());
+    let mut socket = p!(builder.connect("localhost", socket));
+
+    p!(socket.write_all(b"hello"));
+    let mut buf = vec![];
+    p!(socket.read_to_end(&mut buf));
+    assert_eq!(buf, b"world");
+
+    p!(j.join());
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn acceptor_with_protocol_bounds() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+    let legacy = acceptor.with_protocol_bounds(Some(Protocol::Tlsv12), None);
+    let modern = acceptor.with_protocol_bounds(Some(Protocol::Tlsv13), None);
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        for acceptor in [legacy, modern, acceptor] {
+            let socket = p!(listener.accept()).0;
+            if let Ok(mut socket) = acceptor.accept(socket) {
+                p!(socket.write_all(b"hello"));
+            }
+        }
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .max_protocol_version(Some(Protocol::Tlsv12))
+        .build());
+    let connect = || {
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        connector
+            .connect("localhost", socket)
+            .map(|mut socket| p!(socket.read_exact(&mut [0; 5])))
+    };
+
+    p!(connect());
+    connect().unwrap_err();
+    // The original acceptor keeps its own bounds.
+    p!(connect());
+
+    p!(j.join());
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn protocol_version() {
+    let mut protocols = vec![Protocol::Tlsv12];
+    if cfg!(not(any(target_os = "macos", target_os = "ios"))) {
+        protocols.push(Protocol::Tlsv13);
+    }
+
+    for protocol in protocols {
+        let keys = test_cert_gen::keys();
+
+        let identity = p!(Identity::from_pkcs12(
+            &keys.server.cert_and_key_pkcs12.pkcs12.0,
+            &keys.server.cert_and_key_pkcs12.password
+        ));
+        let builder = p!(TlsAcceptor::builder(identity)
+            .min_protocol_version(Some(protocol))
+            .max_protocol_version(Some(protocol))
+            .build());
+
+        let listener = p!(TcpListener::bind("0.0.0.0:0"));
+        let port = p!(listener.local_addr()).port();
+
+        let j = thread::spawn(move || {
+            let socket = p!(listener.accept()).0;
+            let socket = p!(builder.accept(socket));
+            p!(socket.protocol_version())
+        });
+
+        let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let builder = p!(TlsConnector::builder()
+            .add_root_certificate(root_ca)
+            .build());
+        let socket = p!(builder.connect("localhost", socket));
+
+        let version = p!(socket.protocol_version());
+        assert_eq!(version, Some(protocol));
+        assert_eq!(version, p!(j.join()));
+    }
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn session_id() {
+    for protocol in [Protocol::Tlsv12, Protocol::Tlsv13] {
+        let keys = test_cert_gen::keys();
+
+        let identity = p!(Identity::from_pkcs12(
+            &keys.server.cert_and_key_pkcs12.pkcs12.0,
+            &keys.server.cert_and_key_pkcs12.password
+        ));
+        let builder = p!(TlsAcceptor::builder(identity)
+            .min_protocol_version(Some(protocol))
+            .max_protocol_version(Some(protocol))
+            .build());
+
+        let listener = p!(TcpListener::bind("0.0.0.0:0"));
+        let port = p!(listener.local_addr()).port();
+
+        let j = thread::spawn(move || {
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(builder.accept(socket));
+            p!(socket.write_all(b"hello"));
+            p!(socket.session_id())
+        });
+
+        let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let builder = p!(TlsConnector::builder()
+            .add_root_certificate(root_ca)
+            .build());
+        let mut socket = p!(builder.connect("localhost", socket));
+        p!(socket.read_exact(&mut [0; 5]));
+
+        let id = p!(socket.session_id());
+        let server_id = p!(j.join());
+        if protocol == Protocol::Tlsv12 {
+            assert!(!id.unwrap().is_empty());
+        } else {
+            assert_eq!(id, None);
+            assert_eq!(server_id, None);
+        }
+    }
+}
+
+#[test]
+fn server_no_shared_protocol() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::builder(identity)
+        .min_protocol_version(Some(Protocol::Tlsv12))
+        .build());
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        assert!(builder.accept(socket).is_err());
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .min_protocol_version(Some(Protocol::Tlsv11))
+        .max_protocol_version(Some(Protocol::Tlsv11))
+        .build());
+    assert!(builder.connect("localhost", socket).is_err());
+
+    p!(j.join());
+}
+
+#[test]
+fn server_untrusted() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        // FIXME should assert error
+        // https://github.com/steffengy/schannel-rs/issues/20
+        let _ = builder.accept(socket);
+    });
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::new());
+    builder.connect("localhost", socket).unwrap_err();
+
+    p!(j.join());
+}
+
+#[test]
+fn server_untrusted_unverified() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+
+        let mut buf = [0; 5];
+        p!(socket.read_exact(&mut buf));
+        assert_eq!(&buf, b"hello");
+
+        p!(socket.write_all(b"world"));
+    });
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .build());
+    let mut socket = p!(builder.connect("localhost", socket));
+
+    p!(socket.write_all(b"hello"));
+    let mut buf = vec![];
+    p!(socket.read_to_end(&mut buf));
+    assert_eq!(buf, b"world");
+
+    p!(j.join());
+}
+
+#[test]
+fn pinned_public_key() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        p!(socket.write_all(b"hello"));
+
+        let socket = p!(listener.accept()).0;
+        let _ = builder.accept(socket);
+    });
+
+    let spki = ::der::parse_certificate(keys.server.cert_and_key.cert.get_der())
+        .unwrap()
+        .spki;
+    let mut pin = [0; 32];
+    pin.copy_from_slice(&sha256(spki));
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca.clone())
+        .add_pinned_public_key_sha256([0; 32])
+        .add_pinned_public_key_sha256(pin)
+        .build());
+    let mut socket = p!(builder.connect("localhost", socket));
+    let mut buf = [0; 5];
+    p!(socket.read_exact(&mut buf));
+    assert_eq!(&buf, b"hello");
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .add_pinned_public_key_sha256([0; 32])
+        .build());
+    builder.connect("localhost", socket).unwrap_err();
+
+    p!(j.join());
+}
+
+#[test]
+fn pinned_certificate() {
+    let original = p!(Certificate::from_pem(include_bytes!(
+        "../test/rotated/cert.pem"
+    )));
+    let rotated = include_bytes!("../test/rotated/rotated.pem");
+    let identity = p!(Identity::from_pkcs8(
+        rotated,
+        include_bytes!("../test/rotated/key.pem")
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        for _ in 0..3 {
+            let socket = p!(listener.accept()).0;
+            if let Ok(mut socket) = builder.accept(socket) {
+                p!(socket.write_all(b"hello"));
+            }
+        }
+    });
+
+    let original_der = p!(original.to_der());
+    let mut original_pin = [0; 32];
+    original_pin.copy_from_slice(&sha256(&original_der));
+    let mut spki_pin = [0; 32];
+    spki_pin.copy_from_slice(&sha256(
+        ::der::parse_certificate(&original_der).unwrap().spki,
+    ));
+    let rotated_der = p!(p!(Certificate::from_pem(rotated)).to_der());
+    let mut rotated_pin = [0; 32];
+    rotated_pin.copy_from_slice(&sha256(&rotated_der));
+
+    let root_ca = p!(Certificate::from_pem(include_bytes!(
+        "../test/rotated/ca.pem"
+    )));
+    let connect = |builder: &mut TlsConnectorBuilder| {
+        let connector = p!(builder.add_root_certificate(root_ca.clone()).build());
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        connector.connect("localhost", socket).map(|mut socket| {
+            p!(socket.read_exact(&mut [0; 5]));
+        })
+    };
+
+    // The reissued certificate no longer matches a pin on the original, but the key pin still
+    // matches.
+    connect(TlsConnector::builder().add_pinned_certificate_sha256(original_pin)).unwrap_err();
+    p!(connect(
+        TlsConnector::builder()
+            .add_pinned_certificate_sha256(original_pin)
+            .add_pinned_public_key_sha256(spki_pin)
+    ));
+    p!(connect(
+        TlsConnector::builder().add_pinned_certificate_sha256(rotated_pin)
+    ));
+
+    p!(j.join());
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn handshake_events() {
+    for protocol in [Protocol::Tlsv12, Protocol::Tlsv13] {
+        let keys = test_cert_gen::keys();
+
+        let identity = p!(Identity::from_pkcs12(
+            &keys.server.cert_and_key_pkcs12.pkcs12.0,
+            &keys.server.cert_and_key_pkcs12.password
+        ));
+        let builder = p!(TlsAcceptor::builder(identity)
+            .min_protocol_version(Some(protocol))
+            .max_protocol_version(Some(protocol))
+            .build());
+
+        let listener = p!(TcpListener::bind("0.0.0.0:0"));
+        let port = p!(listener.local_addr()).port();
+
+        let j = thread::spawn(move || {
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(builder.accept(socket));
+            p!(socket.write_all(b"hello"));
+        });
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let builder = p!(TlsConnector::builder()
+            .add_root_certificate(root_ca)
+            .on_handshake_event({
+                let events = events.clone();
+                move |event| events.lock().unwrap().push(event)
+            })
+            .build());
+        let mut socket = p!(builder.connect("localhost", socket));
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                HandshakeEvent::ClientHelloSent,
+                HandshakeEvent::ServerHelloReceived,
+                HandshakeEvent::CertificateReceived,
+                HandshakeEvent::Finished,
+            ]
+        );
+
+        // Nothing is reported after the handshake, such as TLS 1.3 session tickets.
+        p!(socket.read_exact(&mut [0; 5]));
+        assert_eq!(events.lock().unwrap().len(), 4);
+
+        p!(j.join());
+    }
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn key_log_callback() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        p!(socket.write_all(b"hello"));
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+    let lines = Arc::new(Mutex::new(vec![]));
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .key_log_callback({
+            let lines = lines.clone();
+            move |line| lines.lock().unwrap().push(line.to_owned())
+        })
+        .build());
+    let mut socket = p!(builder.connect("localhost", socket));
+    let mut buf = [0; 5];
+    p!(socket.read_exact(&mut buf));
+    assert_eq!(&buf, b"hello");
+
+    let lines = lines.lock().unwrap();
+    assert!(!lines.is_empty());
+    for line in lines.iter() {
+        assert!(
+            line.starts_with("CLIENT_")
+                || line.starts_with("SERVER_")
+                || line.starts_with("EXPORTER_"),
+            "{}",
+            line
+        );
+    }
+
+    p!(j.join());
+}
+
+#[test]
+fn import_same_identity_multiple_times() {
+    let keys = test_cert_gen::keys();
+
+    let _ = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let _ = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+
+    let cert = keys.server.cert_and_key.cert.to_pem().into_bytes();
+    let key = rsa_to_pkcs8(&keys.server.cert_and_key.key.to_pem_incorrect()).into_bytes();
+    let _ = p!(Identity::from_pkcs8(&cert, &key));
+    let _ = p!(Identity::from_pkcs8(&cert, &key));
+}
+
+#[test]
+fn from_pkcs8_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let keys = test_cert_gen::keys();
+
+    let cert_path = dir.path().join("cert.pem");
+    let key_path = dir.path().join("key.pem");
+    let bad_path = dir.path().join("bad.pem");
+    p!(fs::write(
+        &cert_path,
+        keys.server.cert_and_key.cert.to_pem()
+    ));
+    p!(fs::write(
+        &key_path,
+        rsa_to_pkcs8(&keys.server.cert_and_key.key.to_pem_incorrect())
+    ));
+    p!(fs::write(&bad_path, "not a key"));
+
+    let _ = p!(Identity::from_pkcs8_files(&cert_path, &key_path));
+
+    let err = Identity::from_pkcs8_files(&cert_path, &dir.path().join("missing.pem"))
+        .err()
+        .unwrap();
+    let io_err = err.source().and_then(|e| e.downcast_ref::<io::Error>());
+    assert_eq!(io_err.map(|e| e.kind()), Some(io::ErrorKind::NotFound));
+
+    let err = Identity::from_pkcs8_files(&cert_path, &bad_path)
+        .err()
+        .unwrap();
+    assert!(err
+        .source()
+        .and_then(|e| e.downcast_ref::<io::Error>())
+        .is_none());
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn from_pkcs8_encrypted() {
+    let keys = test_cert_gen::keys();
+    let cert = keys.server.cert_and_key.cert.to_pem().into_bytes();
+    let rsa_key = keys.server.cert_and_key.key.to_pem_incorrect();
+    let key = rsa_to_encrypted_pkcs8(&rsa_key, "hunter2");
+
+    let identity = p!(Identity::from_pkcs8_encrypted(
+        &cert,
+        key.as_bytes(),
+        "hunter2"
+    ));
+    assert!(Identity::from_pkcs8_encrypted(&cert, key.as_bytes(), "hunter3").is_err());
+    assert!(Identity::from_pkcs8(&cert, key.as_bytes()).is_err());
+    let pkcs8_key = rsa_to_pkcs8(&rsa_key);
+    assert!(Identity::from_pkcs8_encrypted(&cert, pkcs8_key.as_bytes(), "hunter2").is_err());
+
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        p!(socket.write_all(b"hello"));
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let mut socket = p!(builder.connect("localhost", socket));
+    let mut buf = [0; 5];
+    p!(socket.read_exact(&mut buf));
+    assert_eq!(&buf, b"hello");
+
+    p!(j.join());
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn identity_to_pkcs12() {
+    let keys = test_cert_gen::keys();
+    let mut pem = keys.server.cert_and_key.cert.to_pem();
+    pem.push_str(&keys.client.ca.to_pem());
+    let key = rsa_to_pkcs8(&keys.server.cert_and_key.key.to_pem_incorrect());
+    let identity = p!(Identity::from_pkcs8(pem.as_bytes(), key.as_bytes()));
+
+    let pkcs12 = p!(identity.to_pkcs12("hunter2", Some("server")));
+    assert!(Identity::from_pkcs12(&pkcs12, "hunter3").is_err());
+    let identity = p!(Identity::from_pkcs12(&pkcs12, "hunter2"));
+    let pkcs12 = p!(identity.to_pkcs12("hunter2", None));
+    let identity = p!(Identity::from_pkcs12(&pkcs12, "hunter2"));
+
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        p!(socket.write_all(b"hello"));
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let socket = p!(builder.connect("localhost", socket));
+    let chain = p!(socket.peer_certificate_chain());
+    assert_eq!(chain.len(), 2);
+    assert_eq!(p!(chain[1].to_der()), keys.client.ca.get_der());
+
+    p!(j.join());
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn identity_to_pkcs12_exportable() {
+    let keys = test_cert_gen::keys();
+    let pem = keys.server.cert_and_key.cert.to_pem();
+    let key = rsa_to_pkcs8(&keys.server.cert_and_key.key.to_pem_incorrect());
+
+    let identity = Identity::from_pkcs8_with_options(
+        pem.as_bytes(),
+        key.as_bytes(),
+        ImportOptions::new().exportable(true),
+    );
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        let pkcs12 = p!(p!(identity).to_pkcs12("hunter2", None));
+        p!(Identity::from_pkcs12(&pkcs12, "hunter2"));
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        assert!(identity.is_err());
+        let identity = p!(Identity::from_pkcs8(pem.as_bytes(), key.as_bytes()));
+        assert!(identity.to_pkcs12("hunter2", None).is_err());
+    }
+}
+
+#[test]
+#[cfg(target_os = "windows")]
+fn from_pkcs8_deletes_key_containers() {
+    let keys = test_cert_gen::keys();
+    let pem = keys.server.cert_and_key.cert.to_pem();
+    let key = rsa_to_pkcs8(&keys.server.cert_and_key.key.to_pem_incorrect());
+
+    let identities = (0..100)
+        .map(|_| p!(Identity::from_pkcs8(pem.as_bytes(), key.as_bytes())))
+        .collect::<Vec<_>>();
+    assert!(imp::key_containers() >= 100);
+
+    // Clones share the container with the original.
+    let clones = identities.clone();
+    drop(identities);
+    assert!(imp::key_containers() >= 100);
+
+    drop(clones);
+    assert!(imp::key_containers() < 100);
+}
+
+#[test]
+fn from_pkcs8_rejects_rsa_key() {
+    let keys = test_cert_gen::keys();
+    let cert = keys.server.cert_and_key.cert.to_pem().into_bytes();
+    let rsa_key = keys.server.cert_and_key.key.to_pem_incorrect();
+    assert!(Identity::from_pkcs8(&cert, rsa_key.as_bytes()).is_err());
+    let pkcs8_key = rsa_to_pkcs8(&rsa_key);
+    assert!(Identity::from_pkcs8(&cert, pkcs8_key.as_bytes()).is_ok());
+}
+
+#[test]
+fn shutdown() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+
+        let mut buf = [0; 5];
+        p!(socket.read_exact(&mut buf));
+        assert_eq!(&buf, b"hello");
+
+        assert_eq!(p!(socket.read(&mut buf)), 0);
+        p!(socket.shutdown());
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let mut socket = p!(builder.connect("localhost", socket));
+
+    p!(socket.write_all(b"hello"));
+    p!(socket.shutdown());
+
+    p!(j.join());
+}
+
+#[test]
+fn into_inner() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        p!(socket.write_all(b"hello world"));
+
+        let mut socket = socket.into_inner();
+        let mut buf = [0; 3];
+        p!(socket.read_exact(&mut buf));
+        assert_eq!(&buf, b"bye");
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let mut socket = p!(builder.connect("localhost", socket));
+
+    let mut buf = [0; 5];
+    p!(socket.read_exact(&mut buf));
+    assert_eq!(&buf, b"hello");
+
+    let (mut socket, buf) = socket.into_inner_with_buffer();
+    assert_eq!(buf, b" world");
+    p!(socket.write_all(b"bye"));
+
+    p!(j.join());
+}
+
+#[test]
+fn split() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        p!(socket.write_all(b"hello"));
+        let mut buf = [0; 5];
+        p!(socket.read_exact(&mut buf));
+        assert_eq!(&buf, b"world");
+        p!(socket.write_all(b"bye"));
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let socket = p!(builder.connect("localhost", socket));
+
+    let (mut read, mut write) = socket.split();
+    assert!(read.is_pair_of(&write));
+    let reader = thread::spawn(move || {
+        let mut buf = [0; 5];
+        p!(read.read_exact(&mut buf));
+        assert_eq!(&buf, b"hello");
+        read
+    });
+    let writer = thread::spawn(move || {
+        p!(write.write_all(b"world"));
+        p!(write.flush());
+        write
+    });
+    let read = p!(reader.join());
+    let write = p!(writer.join());
+
+    let mut socket = read.unsplit(write);
+    let mut buf = [0; 3];
+    p!(socket.read_exact(&mut buf));
+    assert_eq!(&buf, b"bye");
+
+    p!(j.join());
+}
+
+#[test]
+fn split_write_during_read() {
+    // Reports when the read half starts waiting for the peer.
+    #[derive(Debug)]
+    struct SignallingStream {
+        stream: TcpStream,
+        reading: Mutex<Option<mpsc::Sender<()>>>,
+    }
+
+    impl Read for &SignallingStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if let Some(tx) = self.reading.lock().unwrap().take() {
+                p!(tx.send(()));
+            }
+            (&self.stream).read(buf)
+        }
+    }
+
+    impl Write for &SignallingStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            (&self.stream).write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            (&self.stream).flush()
+        }
+    }
+
+    impl Read for SignallingStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            (&*self).read(buf)
+        }
+    }
+
+    impl Write for SignallingStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            (&*self).write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            (&*self).flush()
+        }
+    }
+
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    // The server only responds once the client has sent its request.
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        let mut buf = [0; 5];
+        p!(socket.read_exact(&mut buf));
+        assert_eq!(&buf, b"hello");
+        p!(socket.write_all(b"world"));
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let socket = SignallingStream {
+        stream: p!(TcpStream::connect(("localhost", port))),
+        reading: Mutex::new(None),
+    };
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let socket = p!(builder.connect("localhost", socket));
+
+    let (tx, rx) = mpsc::channel();
+    *socket.get_ref().reading.lock().unwrap() = Some(tx);
+    let (mut read, mut write) = socket.split();
+    let reader = thread::spawn(move || {
+        let mut buf = [0; 5];
+        p!(read.read_exact(&mut buf));
+        assert_eq!(&buf, b"world");
+    });
+
+    p!(rx.recv());
+    p!(write.write_all(b"hello"));
+    p!(write.flush());
+    p!(reader.join());
+
+    p!(j.join());
+}
+
+#[test]
+fn peek() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        p!(socket.write_all(b"hello"));
+        p!(socket.shutdown());
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let mut socket = p!(builder.connect("localhost", socket));
+
+    let mut peeked = [0; 5];
+    assert_eq!(p!(socket.peek(&mut peeked)), 5);
+    assert_eq!(&peeked, b"hello");
+
+    let mut buf = [0; 5];
+    p!(socket.read_exact(&mut buf));
+    assert_eq!(buf, peeked);
+
+    p!(j.join());
+}
+
+#[test]
+fn byte_counts() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        let mut buf = vec![0; 100_000];
+        p!(socket.read_exact(&mut buf));
+        assert_eq!(socket.bytes_read(), 100_000);
+        p!(socket.write_all(b"hello"));
+        assert_eq!(socket.bytes_written(), 5);
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let mut socket = p!(builder.connect("localhost", socket));
+    assert_eq!(socket.bytes_read(), 0);
+    assert_eq!(socket.bytes_written(), 0);
+
+    p!(socket.write_all(&vec![0; 100_000]));
+    p!(socket.flush());
+    assert_eq!(socket.bytes_written(), 100_000);
+
+    p!(socket.read_exact(&mut [0; 5]));
+    assert_eq!(socket.bytes_read(), 5);
+
+    p!(j.join());
+}
+
+#[test]
+fn stream_role() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        assert!(socket.is_server());
+        assert!(!socket.is_client());
+        p!(socket.write_all(b"hello"));
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let mut socket = p!(builder.connect("localhost", socket));
+    assert!(socket.is_client());
+    assert!(!socket.is_server());
+    p!(socket.read_exact(&mut [0; 5]));
+
+    p!(j.join());
+}
+
+#[test]
+fn read_uninit() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let data = (0..100_000).map(|i| i as u8).collect::<Vec<_>>();
+    let expected = data.clone();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        p!(socket.write_all(&data));
+        p!(socket.shutdown());
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let mut socket = p!(builder.connect("localhost", socket));
+
+    let mut received = vec![];
+    let mut buf = [MaybeUninit::<u8>::uninit(); 4096];
+    loop {
+        let len = p!(socket.read_uninit(&mut buf));
+        if len == 0 {
+            break;
+        }
+        received.extend(buf[..len].iter().map(|b| unsafe { b.assume_init() }));
+    }
+    assert_eq!(received, expected);
+
+    p!(j.join());
+}
+
+// Run with `cargo test --release -- --ignored --nocapture read_uninit_throughput`.
+#[test]
+#[ignore]
+fn read_uninit_throughput() {
+    const TOTAL: usize = 256 * 1024 * 1024;
+    const CHUNK: usize = 64 * 1024;
+
+    fn run(read: fn(&mut TlsStream<TcpStream>) -> usize) -> Duration {
+        let keys = test_cert_gen::keys();
+
+        let identity = p!(Identity::from_pkcs12(
+            &keys.server.cert_and_key_pkcs12.pkcs12.0,
+            &keys.server.cert_and_key_pkcs12.password
+        ));
+        let builder = p!(TlsAcceptor::new(identity));
+
+        let listener = p!(TcpListener::bind("0.0.0.0:0"));
+        let port = p!(listener.local_addr()).port();
+
+        let j = thread::spawn(move || {
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(builder.accept(socket));
+            let chunk = vec![0x5a; CHUNK];
+            for _ in 0..TOTAL / CHUNK {
+                p!(socket.write_all(&chunk));
+            }
+            p!(socket.shutdown());
+        });
+
+        let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let builder = p!(TlsConnector::builder()
+            .add_root_certificate(root_ca)
+            .build());
+        let mut socket = p!(builder.connect("localhost", socket));
+
+        let start = Instant::now();
+        let mut received = 0;
+        loop {
+            let len = read(&mut socket);
+            if len == 0 {
+                break;
+            }
+            received += len;
+        }
+        let elapsed = start.elapsed();
+        assert_eq!(received, TOTAL);
+
+        p!(j.join());
+        elapsed
+    }
+
+    // Each read gets a fresh buffer, as it would when handing chunks off to another task.
+    let zeroed = run(|socket| {
+        let mut buf = vec![0; CHUNK];
+        p!(socket.read(&mut buf))
+    });
+    let uninit = run(|socket| {
+        let mut buf = Vec::<MaybeUninit<u8>>::with_capacity(CHUNK);
+        unsafe { buf.set_len(CHUNK) };
+        p!(socket.read_uninit(&mut buf))
+    });
+
+    let mib = (TOTAL / (1024 * 1024)) as f64;
+    println!(
+        "read: {:.0} MiB/s, read_uninit: {:.0} MiB/s",
+        mib / zeroed.as_secs_f64(),
+        mib / uninit.as_secs_f64()
+    );
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn received_close_notify() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        p!(socket.write_all(b"hello"));
+        p!(socket.shutdown());
+
+        // The second connection is closed without a TLS shutdown.
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        p!(socket.write_all(b"hello"));
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let mut socket = p!(builder.connect("localhost", socket));
+    let mut buf = [0; 5];
+    p!(socket.read_exact(&mut buf));
+    assert!(!p!(socket.received_close_notify()));
+    assert_eq!(p!(socket.read(&mut buf)), 0);
+    assert!(p!(socket.received_close_notify()));
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let mut socket = p!(builder.connect("localhost", socket));
+    p!(socket.read_exact(&mut buf));
+    let _ = socket.read(&mut buf);
+    assert!(!p!(socket.received_close_notify()));
+
+    p!(j.join());
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn client_cert_handshake(mode: ClientCertMode, send_cert: bool) -> Result<Option<Certificate>> {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let client_ca = p!(Certificate::from_pem(include_bytes!(
+        "../test/client-auth/ca.pem"
+    )));
+    let acceptor = p!(TlsAcceptor::builder(identity)
+        .client_cert_verification(mode)
+        .add_client_ca(client_ca)
+        .build());
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = match acceptor.accept(socket) {
+            Ok(socket) => socket,
+            Err(HandshakeError::Failure { error: e, .. }) => return Err(e),
+            Err(HandshakeError::WouldBlock(_)) => panic!("unexpected WouldBlock"),
+        };
+        p!(socket.write_all(b"hello"));
+        socket.peer_certificate()
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let mut builder = TlsConnector::builder();
+    builder.add_root_certificate(root_ca);
+    if send_cert {
+        builder.identity(p!(Identity::from_pkcs8(
+            include_bytes!("../test/client-auth/cert.pem"),
+            include_bytes!("../test/client-auth/key.pem")
+        )));
+    }
+    let connector = p!(builder.build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    // With TLS 1.3 the client only learns that its certificate was rejected once it reads.
+    if let Ok(mut socket) = connector.connect("localhost", socket) {
+        let _ = socket.read_exact(&mut [0; 5]);
+    }
+
+    p!(j.join())
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn client_cert_verification() {
+    assert!(p!(client_cert_handshake(ClientCertMode::None, true)).is_none());
+
+    assert!(p!(client_cert_handshake(ClientCertMode::Optional, false)).is_none());
+    assert!(p!(client_cert_handshake(ClientCertMode::Optional, true)).is_some());
+
+    assert!(client_cert_handshake(ClientCertMode::Required, false).is_err());
+    assert!(p!(client_cert_handshake(ClientCertMode::Required, true)).is_some());
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn client_identity_selected_by_ca() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let client_ca = p!(Certificate::from_pem(include_bytes!(
+        "../test/client-auth/ca.pem"
+    )));
+    let acceptor = p!(TlsAcceptor::builder(identity)
+        .client_cert_verification(ClientCertMode::Required)
+        .add_client_ca(client_ca)
+        .build());
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(acceptor.accept(socket));
+        p!(socket.write_all(b"hello"));
+        p!(socket.peer_certificate())
+    });
+
+    // The first identity was issued by a CA the server doesn't name.
+    let other = p!(Identity::from_pkcs8(
+        include_bytes!("../test/rotated/cert.pem"),
+        include_bytes!("../test/rotated/key.pem")
+    ));
+    let client = p!(Identity::from_pkcs8(
+        include_bytes!("../test/client-auth/cert.pem"),
+        include_bytes!("../test/client-auth/key.pem")
+    ));
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .add_client_identity(other)
+        .add_client_identity(client)
+        .build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let mut socket = p!(connector.connect("localhost", socket));
+    p!(socket.read_exact(&mut [0; 5]));
+
+    let expected = p!(Certificate::from_pem(include_bytes!(
+        "../test/client-auth/cert.pem"
+    )));
+    assert!(p!(j.join()) == Some(expected));
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn client_cert_verify_callback() {
+    let keys = test_cert_gen::keys();
+    let client_ca = p!(Certificate::from_pem(include_bytes!(
+        "../test/client-auth/ca.pem"
+    )));
+
+    for &(subject, authorized) in &[("CN=client", true), ("CN=someone else", false)] {
+        let identity = p!(Identity::from_pkcs12(
+            &keys.server.cert_and_key_pkcs12.pkcs12.0,
+            &keys.server.cert_and_key_pkcs12.password
+        ));
+        let seen = Arc::new(Mutex::new(vec![]));
+        let acceptor = {
+            let seen = seen.clone();
+            p!(TlsAcceptor::builder(identity)
+                .client_cert_verification(ClientCertMode::Required)
+                .add_client_ca(client_ca.clone())
+                .client_cert_verify_callback(move |chain| {
+                    *seen.lock().unwrap() = chain
+                        .iter()
+                        .map(|cert| cert.subject_name().unwrap())
+                        .collect();
+                    if chain[0].subject_name()? == subject {
+                        Ok(())
+                    } else {
+                        Err(Error::from(io::Error::new(
+                            io::ErrorKind::PermissionDenied,
+                            "unauthorized client",
+                        )))
+                    }
+                })
+                .build())
+        };
+
+        let listener = p!(TcpListener::bind("0.0.0.0:0"));
+        let port = p!(listener.local_addr()).port();
+
+        let j = thread::spawn(move || {
+            let socket = p!(listener.accept()).0;
+            let mut socket = acceptor.accept(socket).ok()?;
+            p!(socket.write_all(b"hello"));
+            Some(p!(socket.peer_certificate()).unwrap())
+        });
+
+        // With TLS 1.3 the client only learns that its certificate was rejected once it reads.
+        let connector = p!(TlsConnector::builder()
+            .add_root_certificate(p!(Certificate::from_der(keys.client.ca.get_der())))
+            .max_protocol_version(Some(Protocol::Tlsv12))
+            .identity(p!(Identity::from_pkcs8(
+                include_bytes!("../test/client-auth/cert.pem"),
+                include_bytes!("../test/client-auth/key.pem")
+            )))
+            .build());
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        match connector.connect("localhost", socket) {
+            Ok(mut socket) => {
+                assert!(authorized);
+                let mut buf = vec![];
+                p!(socket.read_to_end(&mut buf));
+                assert_eq!(buf, b"hello");
+            }
+            Err(_) => assert!(!authorized),
+        }
+
+        assert_eq!(p!(j.join()).is_some(), authorized);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                "CN=client".to_string(),
+                "CN=native-tls client CA".to_string()
+            ]
+        );
+    }
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn tls_alert() {
+    const UNKNOWN_CA: u8 = 48;
+
+    let keys = test_cert_gen::keys();
+
+    // Each side rejects the other's certificate in turn.
+    for &client_rejects in &[true, false] {
+        let identity = p!(Identity::from_pkcs12(
+            &keys.server.cert_and_key_pkcs12.pkcs12.0,
+            &keys.server.cert_and_key_pkcs12.password
+        ));
+        let acceptor = p!(TlsAcceptor::builder(identity)
+            .client_cert_verification(ClientCertMode::Required)
+            .build());
+
+        let listener = p!(TcpListener::bind("0.0.0.0:0"));
+        let port = p!(listener.local_addr()).port();
+
+        let j = thread::spawn(move || {
+            let socket = p!(listener.accept()).0;
+            match acceptor.accept(socket) {
+                Ok(_) => panic!("handshake succeeded"),
+                Err(HandshakeError::Failure { error, .. }) => error,
+                Err(HandshakeError::WouldBlock(_)) => panic!("unexpected WouldBlock"),
+            }
+        });
+
+        let mut builder = TlsConnector::builder();
+        // With TLS 1.3 the client only learns that its certificate was rejected once it reads.
+        builder
+            .max_protocol_version(Some(Protocol::Tlsv12))
+            .identity(p!(Identity::from_pkcs8(
+                include_bytes!("../test/client-auth/cert.pem"),
+                include_bytes!("../test/client-auth/key.pem")
+            )));
+        if !client_rejects {
+            builder.add_root_certificate(p!(Certificate::from_der(keys.client.ca.get_der())));
+        }
+        let connector = p!(builder.build());
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let error = match connector.connect("localhost", socket) {
+            Ok(_) => panic!("handshake succeeded"),
+            Err(HandshakeError::Failure { error, .. }) => error,
+            Err(HandshakeError::WouldBlock(_)) => panic!("unexpected WouldBlock"),
+        };
+        assert_eq!(error.tls_alert(), Some(UNKNOWN_CA), "{}", error);
+
+        let error = p!(j.join());
+        assert_eq!(error.tls_alert(), Some(UNKNOWN_CA), "{}", error);
+    }
+
+    let error = Error::from(io::Error::new(io::ErrorKind::Other, "not an alert"));
+    assert_eq!(error.tls_alert(), None);
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn server_name_identity() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let other_cert = include_bytes!("../test/client-auth/cert.pem");
+    let other_identity = p!(Identity::from_pkcs8(
+        other_cert,
+        include_bytes!("../test/client-auth/key.pem")
+    ));
+    let acceptor = p!(TlsAcceptor::builder(identity)
+        .add_server_name_identity("other.test", other_identity)
+        .build());
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        for _ in 0..2 {
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(acceptor.accept(socket));
+            p!(socket.write_all(b"hello"));
+        }
+    });
+
+    let connector = p!(TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .build());
+    let mut certs = vec![];
+    for domain in &["localhost", "OTHER.test"] {
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let mut socket = p!(connector.connect(domain, socket));
+        p!(socket.read_exact(&mut [0; 5]));
+        certs.push(p!(p!(socket.peer_certificate()).unwrap().to_der()));
+    }
+
+    assert_eq!(certs[0], keys.server.cert_and_key.cert.get_der());
+    assert_eq!(certs[1], p!(p!(Certificate::from_pem(other_cert)).to_der()));
+
+    p!(j.join());
+}
+
+#[test]
+fn use_sni() {
+    #[derive(Debug)]
+    struct RecordingReads {
+        stream: TcpStream,
+        read: Vec<u8>,
+    }
+
+    impl Read for RecordingReads {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.stream.read(buf)?;
+            self.read.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+    }
+
+    impl Write for RecordingReads {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.stream.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.stream.flush()
+        }
+    }
+
+    // The domain, use_sni, danger_accept_invalid_hostnames, and whether the handshake succeeds.
+    let cases = [
+        ("localhost", true, false, true),
+        ("localhost", false, false, true),
+        ("wrong.test", false, false, false),
+        ("wrong.test", false, true, true),
+    ];
+
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        (0..cases.len())
+            .map(|_| {
+                let stream = p!(listener.accept()).0;
+                let socket = RecordingReads {
+                    stream,
+                    read: vec![],
+                };
+                match acceptor.accept(socket) {
+                    Ok(mut socket) => {
+                        let _ = socket.write_all(b"hello");
+                        socket.get_ref().read.clone()
+                    }
+                    Err(_) => vec![],
+                }
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    for &(domain, use_sni, accept_invalid_hostnames, succeeds) in &cases {
+        let connector = p!(TlsConnector::builder()
+            .add_root_certificate(root_ca.clone())
+            .use_sni(use_sni)
+            .danger_accept_invalid_hostnames(accept_invalid_hostnames)
+            .build());
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        match connector.connect(domain, socket) {
+            Ok(mut socket) => {
+                assert!(succeeds, "{}", domain);
+                let mut buf = vec![];
+                p!(socket.read_to_end(&mut buf));
+                assert_eq!(buf, b"hello");
+            }
+            Err(_) => assert!(!succeeds, "{}", domain),
+        }
+    }
+
+    let reads = p!(j.join());
+    for (&(domain, use_sni, _, succeeds), read) in cases.iter().zip(reads) {
+        if succeeds {
+            let sent = read.windows(domain.len()).any(|w| w == domain.as_bytes());
+            assert_eq!(sent, use_sni, "{}", domain);
+        }
+    }
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn use_sni_required_by_server() {
+    let keys = test_cert_gen::keys();
+
+    // Only clients which send the right name are presented with a certificate they trust.
+    let identity = p!(Identity::from_pkcs8(
+        include_bytes!("../test/client-auth/cert.pem"),
+        include_bytes!("../test/client-auth/key.pem")
+    ));
+    let sni_identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::builder(identity)
+        .add_server_name_identity("sni.test", sni_identity)
+        .build());
+
+    // use_sni, server_name_indication, and whether the handshake succeeds.
+    let cases = [
+        (true, None, false),
+        (true, Some("sni.test"), true),
+        (false, Some("sni.test"), false),
+    ];
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        for _ in 0..cases.len() {
+            let socket = p!(listener.accept()).0;
+            if let Ok(mut socket) = acceptor.accept(socket) {
+                let _ = socket.write_all(b"hello");
+            }
+        }
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    for &(use_sni, sni, succeeds) in &cases {
+        let connector = p!(TlsConnector::builder()
+            .add_root_certificate(root_ca.clone())
+            .use_sni(use_sni)
+            .server_name_indication(sni.map(str::to_owned))
+            .build());
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        // The server's certificate is always verified against the domain.
+        match connector.connect("localhost", socket) {
+            Ok(mut socket) => {
+                assert!(succeeds, "{} {:?}", use_sni, sni);
+                let mut buf = vec![];
+                p!(socket.read_to_end(&mut buf));
+                assert_eq!(buf, b"hello");
+            }
+            Err(_) => assert!(!succeeds, "{} {:?}", use_sni, sni),
+        }
+    }
+
+    p!(j.join());
+}
+
+#[test]
+fn shutdown_would_block() {
+    #[derive(Debug)]
+    struct BlockingWrites {
+        stream: TcpStream,
+        blocked: usize,
+    }
+
+    impl Read for BlockingWrites {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.stream.read(buf)
+        }
+    }
+
+    impl Write for BlockingWrites {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.blocked > 0 {
+                self.blocked -= 1;
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.stream.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.stream.flush()
+        }
+    }
+
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(acceptor.accept(socket));
+        assert_eq!(p!(socket.read(&mut [0; 5])), 0);
+        #[cfg(not(target_os = "windows"))]
+        assert!(p!(socket.received_close_notify()));
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let stream = BlockingWrites {
+        stream: p!(TcpStream::connect(("localhost", port))),
+        blocked: 0,
+    };
+    let mut socket = p!(connector.connect("localhost", stream));
+
+    socket.get_mut().blocked = 3;
+    let mut would_block = 0;
+    loop {
+        match socket.shutdown() {
+            Ok(()) => break,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => would_block += 1,
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+    assert_eq!(would_block, 3);
+
+    p!(j.join());
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn refresh_keys() {
+    let keys = test_cert_gen::keys();
+
+    for &protocol in &[Protocol::Tlsv12, Protocol::Tlsv13] {
+        let identity = p!(Identity::from_pkcs12(
+            &keys.server.cert_and_key_pkcs12.pkcs12.0,
+            &keys.server.cert_and_key_pkcs12.password
+        ));
+        let acceptor = p!(TlsAcceptor::builder(identity)
+            .allow_renegotiation(true)
+            .build());
+
+        let listener = p!(TcpListener::bind("0.0.0.0:0"));
+        let port = p!(listener.local_addr()).port();
+
+        let j = thread::spawn(move || {
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(acceptor.accept(socket));
+            let mut buf = [0; 5];
+            p!(socket.read_exact(&mut buf));
+            assert_eq!(&buf, b"hello");
+            // The client's refresh is handled while waiting for data.
+            p!(socket.read_exact(&mut buf));
+            assert_eq!(&buf, b"world");
+            p!(socket.write_all(b"done"));
+        });
+
+        let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+        let connector = p!(TlsConnector::builder()
+            .add_root_certificate(root_ca)
+            .max_protocol_version(Some(protocol))
+            .build());
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let mut socket = p!(connector.connect("localhost", socket));
+        assert_eq!(p!(socket.protocol_version()), Some(protocol));
+
+        p!(socket.write_all(b"hello"));
+        p!(socket.refresh_keys());
+        p!(socket.write_all(b"world"));
+        let mut buf = [0; 4];
+        p!(socket.read_exact(&mut buf));
+        assert_eq!(&buf, b"done");
+
+        p!(j.join());
+    }
+}
+
+#[test]
+fn flush_after_write() {
+    #[derive(Debug)]
+    struct BufferedWrites {
+        stream: TcpStream,
+        pending: Vec<u8>,
+    }
+
+    impl Read for BufferedWrites {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.stream.read(buf)
+        }
+    }
+
+    impl Write for BufferedWrites {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.pending.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.stream.write_all(&self.pending)?;
+            self.pending.clear();
+            self.stream.flush()
+        }
+    }
+
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        for _ in 0..2 {
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(acceptor.accept(socket));
+            let mut buf = [0; 5];
+            p!(socket.read_exact(&mut buf));
+            assert_eq!(&buf, b"hello");
+        }
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let mut builder = TlsConnector::builder();
+    builder.add_root_certificate(root_ca);
+
+    for &flush_after_write in &[false, true] {
+        let connector = p!(builder.flush_after_write(flush_after_write).build());
+        let stream = BufferedWrites {
+            stream: p!(TcpStream::connect(("localhost", port))),
+            pending: vec![],
+        };
+        let mut socket = p!(connector.connect("localhost", stream));
+        p!(socket.flush());
+
+        p!(socket.write_all(b"hello"));
+        assert_eq!(socket.get_ref().pending.is_empty(), flush_after_write);
+        if !flush_after_write {
+            p!(socket.flush());
+        }
+    }
+
+    p!(j.join());
+}
+
+#[test]
+fn write_vectored() {
+    #[derive(Debug)]
+    struct RecordingWrites {
+        stream: TcpStream,
+        written: Vec<u8>,
+    }
+
+    impl RecordingWrites {
+        fn records(&mut self) -> usize {
+            let mut records = 0;
+            let mut i = 0;
+            while i + 5 <= self.written.len() {
+                records += 1;
+                i += 5 + u16::from_be_bytes([self.written[i + 3], self.written[i + 4]]) as usize;
+            }
+            self.written.clear();
+            records
+        }
+    }
+
+    impl Read for RecordingWrites {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.stream.read(buf)
+        }
+    }
+
+    impl Write for RecordingWrites {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = self.stream.write(buf)?;
+            self.written.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.stream.flush()
+        }
+    }
+
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(acceptor.accept(socket));
+        let mut buf = [0; 1005];
+        p!(socket.read_exact(&mut buf));
+        assert!(buf.chunks(5).all(|frame| frame == b"frame"));
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let stream = RecordingWrites {
+        stream: p!(TcpStream::connect(("localhost", port))),
+        written: vec![],
+    };
+    let mut socket = p!(connector.connect("localhost", stream));
+    socket.get_mut().written.clear();
+
+    for _ in 0..100 {
+        p!(socket.write_all(b"frame"));
+    }
+    assert_eq!(socket.get_mut().records(), 100);
+
+    let frames = vec![io::IoSlice::new(b"frame"); 100];
+    assert_eq!(p!(socket.write_vectored(&frames)), 500);
+    assert_eq!(socket.get_mut().records(), 1);
+
+    let frames = [
+        io::IoSlice::new(b""),
+        io::IoSlice::new(b"frame"),
+        io::IoSlice::new(b""),
+    ];
+    assert_eq!(p!(socket.write_vectored(&frames)), 5);
+    assert_eq!(socket.get_mut().records(), 1);
+
+    p!(j.join());
+}
+
+#[test]
+fn false_start_unsupported() {
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    match TlsConnector::builder().enable_false_start(true).build() {
+        Ok(connector) => {
+            let socket = p!(TcpStream::connect(("localhost", port)));
+            assert!(connector.connect("localhost", socket).is_err());
+        }
+        Err(e) => assert!(e.to_string().contains("not supported"), "{}", e),
+    }
+
+    p!(TlsConnector::builder().enable_false_start(false).build());
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn max_fragment_length() {
+    #[derive(Debug)]
+    struct RecordingReads {
+        stream: TcpStream,
+        read: Vec<u8>,
+    }
+
+    impl Read for RecordingReads {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.stream.read(buf)?;
+            self.read.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+    }
+
+    impl Write for RecordingReads {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.stream.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.stream.flush()
+        }
+    }
+
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(acceptor.accept(socket));
+        p!(socket.write_all(&[0; 4000]));
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .max_fragment_length(Some(MaxFragmentLength::Bytes512))
+        .build());
+    let stream = RecordingReads {
+        stream: p!(TcpStream::connect(("localhost", port))),
+        read: vec![],
+    };
+    let mut socket = p!(connector.connect("localhost", stream));
+    p!(socket.read_exact(&mut [0; 4000]));
+
+    // Encrypted records may expand the plaintext by up to 256 bytes.
+    let read = &socket.get_ref().read;
+    let mut lengths = vec![];
+    let mut i = 0;
+    while i + 5 <= read.len() {
+        let len = u16::from_be_bytes([read[i + 3], read[i + 4]]) as usize;
+        lengths.push(len);
+        i += 5 + len;
+    }
+    assert!(lengths.len() >= 8, "{:?}", lengths);
+    assert!(lengths.iter().all(|&len| len <= 512 + 256), "{:?}", lengths);
+
+    p!(j.join());
+}
+
+#[test]
+fn mid_handshake_wants() {
+    #[derive(Debug)]
+    struct ControlledStream {
+        stream: TcpStream,
+        block_reads: bool,
+        block_writes: bool,
+    }
+
+    impl Read for ControlledStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.block_reads {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.stream.read(buf)
+        }
+    }
+
+    impl Write for ControlledStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.block_writes {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.stream.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.stream.flush()
+        }
+    }
+
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(acceptor.accept(socket));
+        p!(socket.write_all(b"hello"));
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let stream = ControlledStream {
+        stream: p!(TcpStream::connect(("localhost", port))),
+        block_reads: false,
+        block_writes: true,
+    };
+
+    let mut s = match connector.connect("localhost", stream) {
+        Err(HandshakeError::WouldBlock(s)) => s,
+        _ => panic!("expected a WouldBlock error"),
+    };
+    assert!(s.wants_write());
+    assert!(!s.wants_read());
+
+    s.get_mut().block_writes = false;
+    s.get_mut().block_reads = true;
+    let mut s = match s.handshake() {
+        Err(HandshakeError::WouldBlock(s)) => s,
+        _ => panic!("expected a WouldBlock error"),
+    };
+    assert!(s.wants_read());
+    assert!(!s.wants_write());
+
+    s.get_mut().block_reads = false;
+    let mut socket = p!(s.handshake());
+    p!(socket.read_exact(&mut [0; 5]));
+
+    p!(j.join());
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn requested_client_cas() {
+    // Blocks writes once the server's first flight has been read, pausing the handshake before
+    // the client answers the certificate request.
+    #[derive(Debug)]
+    struct PausingStream {
+        stream: TcpStream,
+        block_writes: bool,
+    }
+
+    impl Read for PausingStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.stream.read(buf)?;
+            self.block_writes = true;
+            Ok(n)
+        }
+    }
+
+    impl Write for PausingStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.block_writes {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.stream.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.stream.flush()
+        }
+    }
+
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let client_ca = p!(Certificate::from_pem(include_bytes!(
+        "../test/client-auth/ca.pem"
+    )));
+    // TLS 1.2 sends the certificate request in the same flight as the server's hello.
+    let acceptor = p!(TlsAcceptor::builder(identity)
+        .max_protocol_version(Some(Protocol::Tlsv12))
+        .client_cert_verification(ClientCertMode::Required)
+        .add_client_ca(client_ca)
+        .build());
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(acceptor.accept(socket));
+        p!(socket.write_all(b"hello"));
+    });
+
+    let client = p!(Identity::from_pkcs8(
+        include_bytes!("../test/client-auth/cert.pem"),
+        include_bytes!("../test/client-auth/key.pem")
+    ));
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .identity(client)
+        .build());
+    let stream = PausingStream {
+        stream: p!(TcpStream::connect(("localhost", port))),
+        block_writes: false,
+    };
+
+    let mut s = match connector.connect("localhost", stream) {
+        Err(HandshakeError::WouldBlock(s)) => s,
+        _ => panic!("expected a WouldBlock error"),
+    };
+    assert_eq!(
+        p!(s.requested_client_cas()),
+        vec!["CN=native-tls client CA".to_string()]
+    );
+
+    s.get_mut().block_writes = false;
+    let mut socket = p!(s.handshake());
+    p!(socket.read_exact(&mut [0; 5]));
+
+    p!(j.join());
+}
+
+#[test]
+fn handshake_timeout() {
+    #[derive(Debug)]
+    struct SilentStream;
+
+    impl Read for SilentStream {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl Write for SilentStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let connector = p!(TlsConnector::builder()
+        .handshake_timeout(Some(Duration::from_millis(100)))
+        .build());
+    match connector.connect("localhost", SilentStream) {
+        Err(HandshakeError::Failure { error: e, stream }) => {
+            let e = e.source().unwrap().downcast_ref::<io::Error>().unwrap();
+            assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+            assert!(stream.is_some());
+        }
+        Err(HandshakeError::WouldBlock(_)) => panic!("unexpected WouldBlock"),
+        Ok(_) => panic!("handshake succeeded"),
+    }
+
+    // Without a timeout the handshake is left to the caller to resume.
+    let connector = p!(TlsConnector::new());
+    match connector.connect("localhost", SilentStream) {
+        Err(HandshakeError::WouldBlock(_)) => {}
+        _ => panic!("expected WouldBlock"),
+    }
+}
+
+#[test]
+fn accept_handshake_timeout() {
+    let keys = test_cert_gen::keys();
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::builder(identity)
+        .handshake_timeout(Some(Duration::from_millis(200)))
+        .build());
+
+    let listener = p!(TcpListener::bind("127.0.0.1:0"));
+    let port = p!(listener.local_addr()).port();
+
+    // The client sends the start of a ClientHello and then stalls until the server hangs up.
+    let j = thread::spawn(move || {
+        let mut socket = p!(TcpStream::connect(("127.0.0.1", port)));
+        p!(socket.write_all(&[0x16, 0x03, 0x01, 0x00, 0x80, 0x01, 0x00]));
+        let _ = socket.read_to_end(&mut vec![]);
+    });
+
+    let socket = p!(listener.accept()).0;
+    p!(socket.set_read_timeout(Some(Duration::from_millis(20))));
+    let start = Instant::now();
+    match acceptor.accept(socket) {
+        Err(HandshakeError::Failure { error: e, stream }) => {
+            let e = e.source().unwrap().downcast_ref::<io::Error>().unwrap();
+            assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+            assert!(stream.is_some());
+        }
+        Err(HandshakeError::WouldBlock(_)) => panic!("unexpected WouldBlock"),
+        Ok(_) => panic!("handshake succeeded"),
+    }
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    p!(j.join());
+}
+
+#[test]
+fn accept_handshake_timeout_trickle() {
+    let keys = test_cert_gen::keys();
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::builder(identity)
+        .handshake_timeout(Some(Duration::from_millis(200)))
+        .build());
+
+    let listener = p!(TcpListener::bind("127.0.0.1:0"));
+    let port = p!(listener.local_addr()).port();
+
+    // The client starts a large ClientHello and then sends it a byte at a time, so that reads
+    // never block for long enough to hit the read timeout.
+    let j = thread::spawn(move || {
+        let mut socket = p!(TcpStream::connect(("127.0.0.1", port)));
+        p!(socket.write_all(&[0x16, 0x03, 0x01, 0x40, 0x00, 0x01, 0x00, 0x3f, 0xfc]));
+        for _ in 0..500 {
+            if socket.write_all(&[0]).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    });
+
+    let socket = p!(listener.accept()).0;
+    p!(socket.set_read_timeout(Some(Duration::from_millis(50))));
+    let start = Instant::now();
+    match acceptor.accept(socket) {
+        Err(HandshakeError::Failure { error: e, .. }) => {
+            let e = e.source().unwrap().downcast_ref::<io::Error>().unwrap();
+            assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        }
+        Err(HandshakeError::WouldBlock(_)) => panic!("unexpected WouldBlock"),
+        Ok(_) => panic!("handshake succeeded"),
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(200));
+    assert!(elapsed < Duration::from_secs(2));
+
+    p!(j.join());
+}
+
+#[test]
+fn ip_address_san() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = Command::new("openssl")
+        .args([
+            "req",
+            "-x509",
+            "-newkey",
+            "rsa:2048",
+            "-nodes",
+            "-subj",
+            "/CN=IP test",
+            "-addext",
+            "subjectAltName = IP:127.0.0.1",
+            "-days",
+            "1",
+            "-keyout",
+            "key.pem",
+            "-out",
+            "cert.pem",
+        ])
+        .current_dir(dir.path())
+        .stderr(Stdio::piped())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let cert = fs::read(dir.path().join("cert.pem")).unwrap();
+    let key = fs::read(dir.path().join("key.pem")).unwrap();
+
+    let acceptor = p!(TlsAcceptor::new(p!(Identity::from_pkcs8(&cert, &key))));
+    let listener = p!(TcpListener::bind("127.0.0.1:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(acceptor.accept(socket));
+        p!(socket.write_all(b"hello"));
+
+        let socket = p!(listener.accept()).0;
+        assert!(acceptor.accept(socket).is_err());
+    });
+
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(p!(Certificate::from_pem(&cert)))
+        .build());
+
+    let socket = p!(TcpStream::connect(("127.0.0.1", port)));
+    let mut socket = p!(connector.connect("127.0.0.1", socket));
+    p!(socket.read_exact(&mut [0; 5]));
+
+    let socket = p!(TcpStream::connect(("127.0.0.1", port)));
+    assert!(connector.connect("127.0.0.2", socket).is_err());
+
+    p!(j.join());
+}
+
+fn accept_with_chain(root: &str, identity: Identity) {
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        p!(socket.write_all(b"hello"));
+    });
+
+    let root_ca = p!(Certificate::from_pem(root.as_bytes()));
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let mut socket = p!(builder.connect("localhost", socket));
+    p!(socket.read_exact(&mut [0; 5]));
+
+    p!(j.join());
+}
+
+#[test]
+fn acceptor_sends_intermediates() {
+    let dir = tempfile::tempdir().unwrap();
+    let (root, intermediate, leaf, key) = gen_chain(dir.path());
+
+    let chain = leaf + &intermediate;
+    let identity = p!(Identity::from_pkcs8(chain.as_bytes(), key.as_bytes()));
+    accept_with_chain(&root, identity);
+}
+
+#[test]
+fn from_pkcs8_leaf_not_first() {
+    let dir = tempfile::tempdir().unwrap();
+    let (root, intermediate, leaf, key) = gen_chain(dir.path());
+
+    let chain = intermediate.clone() + &leaf;
+    let identity = p!(Identity::from_pkcs8(chain.as_bytes(), key.as_bytes()));
+    accept_with_chain(&root, identity);
+
+    assert!(Identity::from_pkcs8(intermediate.as_bytes(), key.as_bytes()).is_err());
+}
+
+#[test]
+fn trust_anchor() {
+    let dir = tempfile::tempdir().unwrap();
+    let (root, intermediate, leaf, key) = gen_chain(dir.path());
+
+    let chain = leaf + &intermediate;
+    let identity = p!(Identity::from_pkcs8(chain.as_bytes(), key.as_bytes()));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        for _ in 0..2 {
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(acceptor.accept(socket));
+            p!(socket.write_all(b"hello"));
+        }
+    });
+
+    let root_ca = p!(Certificate::from_pem(root.as_bytes()));
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca.clone())
+        .build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let mut socket = p!(connector.connect("localhost", socket));
+    p!(socket.read_exact(&mut [0; 5]));
+    let anchor = p!(socket.trust_anchor()).unwrap();
+    assert_eq!(p!(anchor.to_der()), p!(root_ca.to_der()));
+
+    let connector = p!(TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let mut socket = p!(connector.connect("localhost", socket));
+    p!(socket.read_exact(&mut [0; 5]));
+    assert!(p!(socket.trust_anchor()).is_none());
+
+    p!(j.join());
+}
+
+#[test]
+fn max_chain_depth() {
+    let dir = tempfile::tempdir().unwrap();
+    let (root, intermediate, leaf, key) = gen_chain(dir.path());
+
+    let chain = leaf + &intermediate;
+    let identity = p!(Identity::from_pkcs8(chain.as_bytes(), key.as_bytes()));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        assert!(acceptor.accept(socket).is_err());
+
+        for _ in 0..2 {
+            let socket = p!(listener.accept()).0;
+            let mut socket = p!(acceptor.accept(socket));
+            p!(socket.write_all(b"hello"));
+        }
+    });
+
+    let root_ca = p!(Certificate::from_pem(root.as_bytes()));
+    let mut builder = TlsConnector::builder();
+    builder.add_root_certificate(root_ca);
+
+    let connector = p!(builder.max_chain_depth(0).build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    assert!(connector.connect("localhost", socket).is_err());
+
+    // Depths too large for the implementation are clamped rather than truncated.
+    let huge = usize::MAX.checked_shl(32).unwrap_or(usize::MAX);
+    for &depth in &[1, huge] {
+        let connector = p!(builder.max_chain_depth(depth).build());
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        let mut socket = p!(connector.connect("localhost", socket));
+        p!(socket.read_exact(&mut [0; 5]));
+    }
+
+    p!(j.join());
+}
+
+#[test]
+#[cfg(not(feature = "alpn"))]
+fn negotiated_alpn_without_feature() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+        p!(socket.write_all(b"hello"));
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let mut socket = p!(builder.connect("localhost", socket));
+    p!(socket.read_exact(&mut [0; 5]));
+    assert_eq!(p!(socket.negotiated_alpn()), None);
+    assert_eq!(p!(socket.negotiated_alpn_str()), None);
+
+    p!(j.join());
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn ocsp_response_stapled() {
+    let keys = test_cert_gen::keys();
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("cert.pem"),
+        keys.server.cert_and_key.cert.to_pem(),
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("key.pem"),
+        rsa_to_pkcs8(&keys.server.cert_and_key.key.to_pem_incorrect()),
+    )
+    .unwrap();
+    // An OCSPResponse with the tryLater status.
+    let response = [0x30, 0x03, 0x0a, 0x01, 0x03];
+    fs::write(dir.path().join("ocsp.der"), response).unwrap();
+
+    let mut server = Command::new("openssl")
+        .args([
+            "s_server",
+            "-accept",
+            "127.0.0.1:0",
+            "-naccept",
+            "1",
+            "-www",
+            "-cert",
+            "cert.pem",
+            "-key",
+            "key.pem",
+            "-status_file",
+            "ocsp.der",
+        ])
+        .current_dir(dir.path())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdout = io::BufReader::new(server.stdout.take().unwrap());
+    let port = loop {
+        let mut line = String::new();
+        assert!(p!(io::BufRead::read_line(&mut stdout, &mut line)) > 0);
+        if line.starts_with("ACCEPT") {
+            break p!(line.trim().rsplit(':').next().unwrap().parse::<u16>());
+        }
+    };
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .require_stapled_ocsp(true)
+        .build());
+    let socket = p!(TcpStream::connect(("127.0.0.1", port)));
+    let mut socket = p!(connector.connect("localhost", socket));
+    assert_eq!(p!(socket.ocsp_response()), Some(response.to_vec()));
+    p!(socket.shutdown());
+
+    p!(server.wait());
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn ocsp_response_absent() {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(acceptor.accept(socket));
+        p!(socket.write_all(b"hello"));
+
+        let socket = p!(listener.accept()).0;
+        assert!(acceptor.accept(socket).is_err());
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let mut builder = TlsConnector::builder();
+    builder.add_root_certificate(root_ca);
+
+    let connector = p!(builder.build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let mut socket = p!(connector.connect("localhost", socket));
+    p!(socket.read_exact(&mut [0; 5]));
+    assert_eq!(p!(socket.ocsp_response()), None);
+
+    let connector = p!(builder.require_stapled_ocsp(true).build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    assert!(connector.connect("localhost", socket).is_err());
+
+    p!(j.join());
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn must_staple_without_response() {
+    let cert = p!(Certificate::from_pem(include_bytes!(
+        "../test/must-staple/cert.pem"
+    )));
+    assert!(p!(cert.has_must_staple()));
+    let ca = p!(Certificate::from_pem(include_bytes!(
+        "../test/must-staple/ca.pem"
+    )));
+    assert!(!p!(ca.has_must_staple()));
+
+    let identity = p!(Identity::from_pkcs8(
+        include_bytes!("../test/must-staple/cert.pem"),
+        include_bytes!("../test/must-staple/key.pem")
+    ));
+    // The server has no OCSP response to staple.
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        assert!(acceptor.accept(socket).is_err());
+
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(acceptor.accept(socket));
+        p!(socket.write_all(b"hello"));
+    });
+
+    let mut builder = TlsConnector::builder();
+    builder.add_root_certificate(ca);
+
+    let connector = p!(builder.build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    assert!(connector.connect("localhost", socket).is_err());
+
+    let connector = p!(builder.danger_accept_invalid_certs(true).build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let mut socket = p!(connector.connect("localhost", socket));
+    p!(socket.read_exact(&mut [0; 5]));
+    assert_eq!(p!(socket.ocsp_response()), None);
+
+    p!(j.join());
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn acceptor_ocsp_response() {
+    let keys = test_cert_gen::keys();
+    // An OCSPResponse with the tryLater status.
+    let response = vec![0x30, 0x03, 0x0a, 0x01, 0x03];
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::builder(identity)
+        .ocsp_response(response.clone())
+        .build());
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(acceptor.accept(socket));
+        p!(socket.write_all(b"hello"));
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .require_stapled_ocsp(true)
+        .build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let mut socket = p!(connector.connect("localhost", socket));
+    p!(socket.read_exact(&mut [0; 5]));
+    assert_eq!(p!(socket.ocsp_response()), Some(response));
+
+    p!(j.join());
+}
+
+#[cfg(all(
+    feature = "alpn",
+    not(any(target_os = "macos", target_os = "ios", target_os = "windows"))
+))]
+fn alpn_handshake(client_protocols: &[&str]) -> Result<Option<Vec<u8>>> {
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::builder(identity)
+        .alpn_protocols(&["h2", "http/1.1"])
+        .build());
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        if let Ok(mut socket) = acceptor.accept(socket) {
+            p!(socket.write_all(b"hello"));
+        }
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .request_alpns(client_protocols)
+        .build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let result = match connector.connect("localhost", socket) {
+        Ok(mut socket) => {
+            p!(socket.read_exact(&mut [0; 5]));
+            let alpn = socket.negotiated_alpn();
+            if let Ok(ref alpn) = alpn {
+                let expected = alpn.as_ref().map(|p| str::from_utf8(p).unwrap());
+                assert_eq!(p!(socket.negotiated_alpn_str()), expected);
+            }
+            alpn
+        }
+        Err(HandshakeError::Failure { error, .. }) => Err(error),
+        Err(HandshakeError::WouldBlock(_)) => panic!("unexpected WouldBlock"),
+    };
+
+    p!(j.join());
+    result
+}
+
+#[test]
+#[cfg(all(
+    feature = "alpn",
+    not(any(target_os = "macos", target_os = "ios", target_os = "windows"))
+))]
+fn alpn_server() {
+    assert_eq!(
+        p!(alpn_handshake(&["http/1.1", "h2"])),
+        Some(b"h2".to_vec())
+    );
+    assert_eq!(
+        p!(alpn_handshake(&["spdy/1", "http/1.1"])),
+        Some(b"http/1.1".to_vec())
+    );
+    assert_eq!(p!(alpn_handshake(&[])), None);
+}
+
+#[test]
+#[cfg(all(
+    feature = "alpn",
+    not(any(target_os = "macos", target_os = "ios", target_os = "windows"))
+))]
+fn alpn_server_no_overlap() {
+    assert!(alpn_handshake(&["spdy/1"]).is_err());
+}
+
+#[test]
+#[cfg(feature = "alpn")]
+fn require_alpn() {
+    let keys = test_cert_gen::keys();
+
+    for &require in &[false, true] {
+        let identity = p!(Identity::from_pkcs12(
+            &keys.server.cert_and_key_pkcs12.pkcs12.0,
+            &keys.server.cert_and_key_pkcs12.password
+        ));
+        // The server doesn't support ALPN, so it ignores the client's request.
+        let acceptor = p!(TlsAcceptor::new(identity));
+
+        let listener = p!(TcpListener::bind("0.0.0.0:0"));
+        let port = p!(listener.local_addr()).port();
+
+        let j = thread::spawn(move || {
+            let socket = p!(listener.accept()).0;
+            if let Ok(mut socket) = acceptor.accept(socket) {
+                let _ = socket.write_all(b"hello");
+            }
+        });
+
+        let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+        let connector = p!(TlsConnector::builder()
+            .add_root_certificate(root_ca)
+            .request_alpns(&["h2"])
+            .require_alpn(require)
+            .build());
+        let socket = p!(TcpStream::connect(("localhost", port)));
+        match connector.connect("localhost", socket) {
+            Ok(mut socket) => {
+                assert!(!require);
+                p!(socket.read_exact(&mut [0; 5]));
+                assert_eq!(p!(socket.negotiated_alpn()), None);
+            }
+            Err(HandshakeError::Failure { .. }) => assert!(require),
+            Err(HandshakeError::WouldBlock(_)) => panic!("unexpected WouldBlock"),
+        }
+
+        p!(j.join());
+    }
+}
+
 #[test]
 #[cfg(feature = "alpn")]
 fn alpn_google_h2() {
@@ -435,6 +4688,43 @@ fn alpn_google_none() {
     assert_eq!(alpn, None);
 }
 
+#[test]
+fn server_pkcs8_der() {
+    let keys = test_cert_gen::keys();
+    let cert = keys.server.cert_and_key.cert.get_der();
+    let key = rsa_to_pkcs8_der(&keys.server.cert_and_key.key.to_pem_incorrect());
+
+    let identity = p!(Identity::from_pkcs8_der(cert, &key));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(acceptor.accept(socket));
+        p!(socket.write_all(b"hello"));
+    });
+
+    let root_ca = p!(Certificate::from_der(keys.client.ca.get_der()));
+    let connector = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let mut socket = p!(connector.connect("localhost", socket));
+    let mut buf = vec![];
+    p!(socket.read_to_end(&mut buf));
+    assert_eq!(buf, b"hello");
+    assert_eq!(
+        p!(p!(socket.peer_certificate()).unwrap().to_der()),
+        cert.to_vec()
+    );
+
+    p!(j.join());
+
+    assert!(Identity::from_pkcs8_der(cert, b"not a key").is_err());
+}
+
 #[test]
 fn server_pkcs8() {
     let keys = test_cert_gen::keys();
@@ -556,6 +4846,340 @@ fn two_servers() {
     p!(j2.join());
 }
 
+#[test]
+fn certificate_to_pem() {
+    let keys = test_cert_gen::keys();
+    // test-cert-gen writes CRLF line endings.
+    let pem = keys.client.ca.to_pem();
+    assert!(pem.contains("\r\n"));
+
+    let cert = p!(Certificate::from_pem(pem.as_bytes()));
+    let encoded = p!(cert.to_pem());
+    assert_eq!(encoded, pem.replace("\r\n", "\n").into_bytes());
+
+    let cert = p!(Certificate::from_pem(&encoded));
+    assert_eq!(p!(cert.to_der()), keys.client.ca.get_der());
+}
+
+#[test]
+fn certificate_from_pem_bundle() {
+    let keys = test_cert_gen::keys();
+    let bundle = format!(
+        "{}{}{}",
+        keys.client.ca.to_pem(),
+        keys.server.cert_and_key.key.to_pem_incorrect(),
+        keys.server.cert_and_key.cert.to_pem(),
+    );
+
+    let certs = p!(Certificate::from_pem_bundle(bundle.as_bytes()))
+        .iter()
+        .map(|cert| p!(cert.to_der()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        certs,
+        [
+            keys.client.ca.get_der().to_vec(),
+            keys.server.cert_and_key.cert.get_der().to_vec(),
+        ]
+    );
+
+    assert!(p!(Certificate::from_pem_bundle(b"")).is_empty());
+}
+
+#[test]
+fn root_certificates_pem() {
+    let keys = test_cert_gen::keys();
+    let bundle = format!(
+        "{}{}{}{}",
+        keys.client.ca.to_pem(),
+        keys.server.cert_and_key.key.to_pem_incorrect(),
+        keys.server.cert_and_key.cert.to_pem(),
+        include_str!("../test/revocation/ca.pem"),
+    );
+
+    let mut builder = TlsConnector::builder();
+    p!(builder.add_root_certificates_pem(bundle.as_bytes()));
+    let roots = builder
+        .root_certificates
+        .iter()
+        .map(|cert| p!(cert.to_der()))
+        .collect::<Vec<_>>();
+    let revocation_ca = p!(Certificate::from_pem(include_bytes!(
+        "../test/revocation/ca.pem"
+    )));
+    assert_eq!(
+        roots,
+        [
+            keys.client.ca.get_der().to_vec(),
+            keys.server.cert_and_key.cert.get_der().to_vec(),
+            p!(revocation_ca.to_der()),
+        ]
+    );
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let acceptor = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(acceptor.accept(socket));
+        p!(socket.write_all(b"hello"));
+    });
+
+    let connector = p!(builder.build());
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    let mut socket = p!(connector.connect("localhost", socket));
+    let mut buf = vec![];
+    p!(socket.read_to_end(&mut buf));
+    assert_eq!(buf, b"hello");
+
+    p!(j.join());
+}
+
+#[test]
+#[cfg(feature = "tokio")]
+fn connect_async() {
+    use tokio::io::{AsyncRead, AsyncWrite};
+
+    let keys = test_cert_gen::keys();
+
+    let identity = p!(Identity::from_pkcs12(
+        &keys.server.cert_and_key_pkcs12.pkcs12.0,
+        &keys.server.cert_and_key_pkcs12.password
+    ));
+    let builder = p!(TlsAcceptor::new(identity));
+
+    let listener = p!(TcpListener::bind("0.0.0.0:0"));
+    let port = p!(listener.local_addr()).port();
+
+    let j = thread::spawn(move || {
+        let socket = p!(listener.accept()).0;
+        let mut socket = p!(builder.accept(socket));
+
+        let mut buf = [0; 5];
+        p!(socket.read_exact(&mut buf));
+        assert_eq!(&buf, b"hello");
+
+        p!(socket.write_all(b"world"));
+    });
+
+    let root_ca = Certificate::from_der(keys.client.ca.get_der()).unwrap();
+
+    let socket = p!(TcpStream::connect(("localhost", port)));
+    p!(socket.set_nonblocking(true));
+    let builder = p!(TlsConnector::builder()
+        .add_root_certificate(root_ca)
+        .build());
+    let mut socket = p!(block_on(
+        builder.connect_async("localhost", NonblockingStream(socket))
+    ));
+
+    let mut buf = b"hello".to_vec();
+    while !buf.is_empty() {
+        let n = p!(block_on(poll_fn(|cx| {
+            Pin::new(&mut socket).poll_write(cx, &buf)
+        })));
+        buf.drain(..n);
+    }
+
+    let mut buf = vec![];
+    loop {
+        let mut chunk = [0; 16];
+        let mut read_buf = tokio::io::ReadBuf::new(&mut chunk);
+        p!(block_on(poll_fn(|cx| {
+            Pin::new(&mut socket).poll_read(cx, &mut read_buf)
+        })));
+        if read_buf.filled().is_empty() {
+            break;
+        }
+        buf.extend_from_slice(read_buf.filled());
+    }
+    assert_eq!(buf, b"world");
+
+    p!(j.join());
+}
+
+// A minimal executor, so the tests don't need a runtime.
+#[cfg(feature = "tokio")]
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    let waker = Waker::from(Arc::new(NoopWaker));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::yield_now();
+    }
+}
+
+#[cfg(feature = "tokio")]
+fn poll_fn<T, F>(f: F) -> impl Future<Output = T>
+where
+    F: FnMut(&mut Context) -> Poll<T> + Unpin,
+{
+    struct PollFn<F>(F);
+
+    impl<T, F> Future for PollFn<F>
+    where
+        F: FnMut(&mut Context) -> Poll<T> + Unpin,
+    {
+        type Output = T;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+            (self.get_mut().0)(cx)
+        }
+    }
+
+    PollFn(f)
+}
+
+#[cfg(feature = "tokio")]
+struct NonblockingStream(TcpStream);
+
+#[cfg(feature = "tokio")]
+fn nonblocking<T>(r: io::Result<T>) -> Poll<io::Result<T>> {
+    match r {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+        r => Poll::Ready(r),
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncRead for NonblockingStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _: &mut Context,
+        buf: &mut tokio::io::ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let n = match nonblocking(self.get_mut().0.read(buf.initialize_unfilled())) {
+            Poll::Ready(Ok(n)) => n,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        };
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncWrite for NonblockingStream {
+    fn poll_write(self: Pin<&mut Self>, _: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        nonblocking(self.get_mut().0.write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        nonblocking(self.get_mut().0.flush())
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(self.get_mut().0.shutdown(std::net::Shutdown::Write))
+    }
+}
+
+// Generates a root, an intermediate and a leaf for localhost, returning their PEM along with the
+// leaf's key.
+fn gen_chain(dir: &Path) -> (String, String, String, String) {
+    let openssl = |args: &[&str]| {
+        let output = Command::new("openssl")
+            .args(args)
+            .current_dir(dir)
+            .stderr(Stdio::piped())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+    };
+
+    fs::write(
+        dir.join("ext.cnf"),
+        "[ca]\n\
+         basicConstraints = critical, CA:TRUE\n\
+         keyUsage = critical, keyCertSign, cRLSign\n\
+         [leaf]\n\
+         basicConstraints = CA:FALSE\n\
+         keyUsage = critical, digitalSignature, keyEncipherment\n\
+         extendedKeyUsage = serverAuth\n\
+         subjectAltName = DNS:localhost\n",
+    )
+    .unwrap();
+
+    for name in &["root", "intermediate", "leaf"] {
+        openssl(&[
+            "req",
+            "-new",
+            "-newkey",
+            "rsa:2048",
+            "-nodes",
+            "-keyout",
+            &format!("{}.key", name),
+            "-out",
+            &format!("{}.csr", name),
+            "-subj",
+            &format!("/CN={}", name),
+        ]);
+    }
+    openssl(&[
+        "x509",
+        "-req",
+        "-in",
+        "root.csr",
+        "-signkey",
+        "root.key",
+        "-days",
+        "30",
+        "-extfile",
+        "ext.cnf",
+        "-extensions",
+        "ca",
+        "-out",
+        "root.pem",
+    ]);
+    for &(name, issuer, extensions) in &[
+        ("intermediate", "root", "ca"),
+        ("leaf", "intermediate", "leaf"),
+    ] {
+        openssl(&[
+            "x509",
+            "-req",
+            "-in",
+            &format!("{}.csr", name),
+            "-CA",
+            &format!("{}.pem", issuer),
+            "-CAkey",
+            &format!("{}.key", issuer),
+            "-set_serial",
+            "2",
+            "-days",
+            "30",
+            "-extfile",
+            "ext.cnf",
+            "-extensions",
+            extensions,
+            "-out",
+            &format!("{}.pem", name),
+        ]);
+    }
+
+    let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+    (
+        read("root.pem"),
+        read("intermediate.pem"),
+        read("leaf.pem"),
+        rsa_to_pkcs8(&read("leaf.key")),
+    )
+}
+
 fn rsa_to_pkcs8(pem: &str) -> String {
     let mut child = Command::new("openssl")
         .arg("pkcs8")
@@ -571,3 +5195,61 @@ fn rsa_to_pkcs8(pem: &str) -> String {
     }
     String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap()
 }
+
+fn rsa_to_pkcs8_der(pem: &str) -> Vec<u8> {
+    let mut child = Command::new("openssl")
+        .arg("pkcs8")
+        .arg("-topk8")
+        .arg("-nocrypt")
+        .arg("-outform")
+        .arg("DER")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    {
+        let child_stdin = child.stdin.as_mut().unwrap();
+        child_stdin.write_all(pem.as_bytes()).unwrap();
+    }
+    child.wait_with_output().unwrap().stdout
+}
+
+#[cfg(not(target_os = "windows"))]
+fn rsa_to_encrypted_pkcs8(pem: &str, passphrase: &str) -> String {
+    let mut child = Command::new("openssl")
+        .arg("pkcs8")
+        .arg("-topk8")
+        .arg("-v2")
+        .arg("aes-256-cbc")
+        .arg("-passout")
+        .arg(format!("pass:{}", passphrase))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    {
+        let child_stdin = child.stdin.as_mut().unwrap();
+        child_stdin.write_all(pem.as_bytes()).unwrap();
+    }
+    String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap()
+}
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    digest("sha256", data)
+}
+
+fn digest(algorithm: &str, data: &[u8]) -> Vec<u8> {
+    let mut child = Command::new("openssl")
+        .arg("dgst")
+        .arg(format!("-{}", algorithm))
+        .arg("-binary")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    {
+        let child_stdin = child.stdin.as_mut().unwrap();
+        child_stdin.write_all(data).unwrap();
+    }
+    child.wait_with_output().unwrap().stdout
+}